@@ -0,0 +1,137 @@
+//! Times a full scan of a generated library of tagged WAV files: first with a
+//! single worker reading tags and a transaction for every track, as scans
+//! used to work, then with tracks saved in batches, and then with a worker
+//! per CPU as well.
+//!
+//! Run with `cargo bench --bench scan -- [number of tracks]`. The generated
+//! library is kept in the temp directory and reused between runs.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use lofty::{Accessor, Tag, TagExt, TagType};
+use tome::library::{
+    sync_files, FileFilter, ScanMonitor, SCAN_BATCH_SIZE_SETTING, SCAN_WORKERS_SETTING,
+};
+use tome::models::LibraryRoot;
+use tome::store::Store;
+
+const DEFAULT_TRACKS: usize = 2000;
+const TRACKS_PER_ALBUM: usize = 12;
+const SAMPLE_RATE: u32 = 8000;
+
+#[tokio::main]
+async fn main() -> tome::Result<()> {
+    let tracks = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_TRACKS);
+
+    let dir = env::temp_dir().join("tome-bench");
+    let library = dir.join(format!("library-{}", tracks));
+    if !library.is_dir() {
+        println!("generating {} tracks in {}", tracks, library.display());
+        generate_library(&library, tracks)?;
+    }
+
+    let root = LibraryRoot {
+        path: library.clone(),
+        follow_symlinks: false,
+        include_hidden: false,
+        allowed_extensions: vec![],
+        denied_extensions: vec![],
+        exclude_globs: vec![],
+    };
+    let filter = FileFilter::new(&root, &["wav".to_string()])?;
+
+    // Workers, and tracks saved per transaction where 0 is the default
+    for (workers, batch_size) in [(1, 1), (1, 0), (0, 0)] {
+        let db = dir.join(format!("scan-{}-{}.db", workers, batch_size));
+        if db.exists() {
+            fs::remove_file(&db)?;
+        }
+        env::set_var("DATABASE_URL", format!("sqlite://{}", db.display()));
+        let store = Store::new().await?;
+        store.set_setting(SCAN_WORKERS_SETTING, &workers).await?;
+        if batch_size > 0 {
+            store
+                .set_setting(SCAN_BATCH_SIZE_SETTING, &batch_size)
+                .await?;
+        }
+
+        let start = Instant::now();
+        let files = filter.get_audio_files(&library);
+        let mut monitor = ScanMonitor::silent();
+        let changes = sync_files(&store, &[library.clone()], files, &mut monitor).await?;
+        let elapsed = start.elapsed();
+
+        let label = match (workers, batch_size) {
+            (1, 1) => "1 worker, unbatched".to_string(),
+            (0, _) => "one worker per CPU".to_string(),
+            (n, _) => format!("{} worker(s)", n),
+        };
+        println!(
+            "{:>20}: added {} tracks in {:.2?} ({:.0} tracks/s, {} errors)",
+            label,
+            changes.added.len(),
+            elapsed,
+            changes.added.len() as f64 / elapsed.as_secs_f64(),
+            monitor.progress.errors,
+        );
+    }
+    Ok(())
+}
+
+fn generate_library(library: &Path, tracks: usize) -> tome::Result<()> {
+    for i in 0..tracks {
+        let album = i / TRACKS_PER_ALBUM;
+        let number = i % TRACKS_PER_ALBUM + 1;
+        let dir: PathBuf = library
+            .join(format!("Artist {:03}", album % 50))
+            .join(format!("Album {:04}", album));
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{:02} Track.wav", number));
+        write_wav(&path, i as u32)?;
+
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_title(format!("Track {}", i));
+        tag.set_artist(format!("Artist {:03}", album % 50));
+        tag.set_album(format!("Album {:04}", album));
+        tag.set_genre("Rock".to_string());
+        tag.set_track(number as u32);
+        tag.set_year(1970 + (album % 50) as u32);
+        tag.save_to_path(&path)?;
+    }
+    Ok(())
+}
+
+/// Writes a second of 16 bit mono audio, filled with `seed` so every
+/// generated track has different content.
+fn write_wav(path: &Path, seed: u32) -> std::io::Result<()> {
+    let samples: Vec<u8> = seed
+        .to_le_bytes()
+        .iter()
+        .copied()
+        .cycle()
+        .take(SAMPLE_RATE as usize * 2)
+        .collect();
+
+    let mut wav = Vec::with_capacity(44 + samples.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&samples);
+    fs::write(path, wav)
+}
@@ -0,0 +1,4 @@
+ALTER TABLE track ADD COLUMN file_size INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE track ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX track_path ON track (path);
@@ -0,0 +1,15 @@
+CREATE TABLE library_root (
+    path TEXT NOT NULL PRIMARY KEY,
+    follow_symlinks BOOLEAN NOT NULL DEFAULT FALSE,
+    include_hidden BOOLEAN NOT NULL DEFAULT FALSE,
+    -- JSON arrays of strings
+    allowed_extensions TEXT NOT NULL DEFAULT '[]',
+    denied_extensions TEXT NOT NULL DEFAULT '[]',
+    exclude_globs TEXT NOT NULL DEFAULT '[]'
+);
+
+CREATE TABLE setting (
+    key TEXT NOT NULL PRIMARY KEY,
+    -- JSON encoded
+    value TEXT NOT NULL
+);
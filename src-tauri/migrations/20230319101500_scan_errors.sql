@@ -0,0 +1,6 @@
+CREATE TABLE scan_error (
+    path TEXT NOT NULL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    occurred_at INTEGER NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Identifies a track by its audio, ignoring tags. Tracks added from now on
+-- use this as their id too, while existing tracks keep their ids so their
+-- playlist entries survive. Their hashes are filled in on the next scan.
+ALTER TABLE track ADD COLUMN content_hash TEXT;
+
+CREATE INDEX track_content_hash ON track (content_hash);
@@ -0,0 +1,21 @@
+ALTER TABLE track ADD COLUMN album_artist TEXT;
+ALTER TABLE track ADD COLUMN compilation BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE track ADD COLUMN musicbrainz_album_id TEXT;
+
+ALTER TABLE album ADD COLUMN year INTEGER;
+ALTER TABLE album ADD COLUMN compilation BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE album ADD COLUMN musicbrainz_id TEXT;
+ALTER TABLE album ADD COLUMN directory TEXT;
+
+-- Fill in what can be worked out from the tracks already stored. Trimming
+-- every character that isn't a slash from the end of a path leaves its folder.
+UPDATE album SET
+    year = (SELECT MIN(year) FROM track WHERE track.album_id = album.id),
+    directory = (
+        SELECT rtrim(rtrim(path, replace(path, '/', '')), '/')
+        FROM track WHERE track.album_id = album.id LIMIT 1
+    );
+
+-- Album ids now depend on tags that weren't stored before, so have the next
+-- scan re-read every track, which moves it into its new album
+UPDATE track SET modified = 0;
@@ -0,0 +1,16 @@
+-- Counts kept on the album drift as tracks come and go, so work them out
+-- from the tracks instead
+ALTER TABLE album DROP COLUMN track_count;
+
+CREATE VIEW album_summary AS
+SELECT
+    album.*,
+    COUNT(track.id) AS track_count,
+    COALESCE(SUM(track.duration), 0) AS total_duration,
+    -- Tracks without a disc number count as being on the first disc
+    COUNT(DISTINCT CASE WHEN track.id IS NOT NULL THEN COALESCE(track.cd_number, 1) END) AS disc_count,
+    MIN(track.year) AS first_year,
+    MAX(track.year) AS last_year
+FROM album
+LEFT JOIN track ON track.album_id = album.id
+GROUP BY album.id;
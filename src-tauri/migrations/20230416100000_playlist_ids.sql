@@ -0,0 +1,31 @@
+-- Playlists were keyed by their title, so renaming one meant rewriting all of
+-- its entries. Give them their own ids instead.
+CREATE TABLE new_playlist (
+    id INTEGER NOT NULL PRIMARY KEY,
+    title TEXT NOT NULL
+);
+
+CREATE TABLE new_playlist_track (
+    playlist_id INTEGER NOT NULL REFERENCES new_playlist,
+    track_id TEXT NOT NULL REFERENCES track,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (playlist_id, track_id)
+);
+
+INSERT INTO new_playlist (title) SELECT title FROM playlist;
+
+-- Number the entries from 0 in their existing order, closing any gaps
+INSERT INTO new_playlist_track (playlist_id, track_id, position)
+SELECT
+    new_playlist.id,
+    playlist_track.track_id,
+    ROW_NUMBER() OVER (PARTITION BY playlist_track.playlist ORDER BY playlist_track.position) - 1
+FROM playlist_track
+JOIN new_playlist ON new_playlist.title = playlist_track.playlist;
+
+DROP TABLE playlist_track;
+DROP TABLE playlist;
+ALTER TABLE new_playlist RENAME TO playlist;
+ALTER TABLE new_playlist_track RENAME TO playlist_track;
+
+CREATE INDEX playlist_track_position ON playlist_track (playlist_id, position);
@@ -0,0 +1,18 @@
+-- Give each playlist entry its own id so a track can be in a playlist more
+-- than once
+CREATE TABLE new_playlist_track (
+    id INTEGER NOT NULL PRIMARY KEY,
+    playlist_id INTEGER NOT NULL REFERENCES playlist,
+    track_id TEXT NOT NULL REFERENCES track,
+    position INTEGER NOT NULL
+);
+
+INSERT INTO new_playlist_track (playlist_id, track_id, position)
+SELECT playlist_id, track_id, position FROM playlist_track
+ORDER BY playlist_id, position;
+
+DROP TABLE playlist_track;
+ALTER TABLE new_playlist_track RENAME TO playlist_track;
+
+CREATE INDEX playlist_track_position ON playlist_track (playlist_id, position);
+CREATE INDEX playlist_track_track ON playlist_track (track_id);
@@ -0,0 +1,7 @@
+-- JSON encoded rules for smart playlists, which have no entries of their own
+ALTER TABLE playlist ADD COLUMN rules TEXT;
+
+-- When a track was first scanned, in seconds since the Unix epoch. Tracks
+-- already in the library count as added now.
+ALTER TABLE track ADD COLUMN added_at INTEGER NOT NULL DEFAULT 0;
+UPDATE track SET added_at = CAST(strftime('%s', 'now') AS INTEGER);
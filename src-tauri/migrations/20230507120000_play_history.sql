@@ -0,0 +1,29 @@
+CREATE TABLE play_history (
+    id INTEGER NOT NULL PRIMARY KEY,
+    track_id TEXT NOT NULL REFERENCES track,
+    -- Seconds since the Unix epoch
+    played_at INTEGER NOT NULL,
+    -- How many seconds were listened to
+    listened INTEGER NOT NULL,
+    skipped BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX play_history_track ON play_history (track_id, played_at);
+
+-- Totals kept up to date from the history, so listing tracks doesn't have to
+-- add it all up each time
+ALTER TABLE track ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE track ADD COLUMN skip_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE track ADD COLUMN last_played INTEGER;
+
+CREATE TRIGGER play_history_insert AFTER INSERT ON play_history
+BEGIN
+    UPDATE track SET
+        play_count = play_count + (NOT NEW.skipped),
+        skip_count = skip_count + NEW.skipped,
+        last_played = CASE
+            WHEN NEW.skipped THEN last_played
+            ELSE MAX(COALESCE(last_played, 0), NEW.played_at)
+        END
+    WHERE id = NEW.track_id;
+END;
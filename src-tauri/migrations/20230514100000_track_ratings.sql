@@ -0,0 +1,3 @@
+-- From 0 to 5 stars, or NULL if unrated
+ALTER TABLE track ADD COLUMN rating INTEGER;
+ALTER TABLE track ADD COLUMN loved BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,75 @@
+-- Full-text indexes for searching the library. Each row shares its rowid with
+-- the row it indexes, so the triggers can find it without a scan. Diacritics
+-- are folded away, so "bjork" finds "Björk".
+CREATE VIRTUAL TABLE track_search USING fts5 (
+    title,
+    artist,
+    song_artist,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE album_search USING fts5 (
+    title,
+    artist,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE artist_search USING fts5 (
+    name,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO track_search (rowid, title, artist, song_artist)
+SELECT rowid, title, artist, song_artist FROM track;
+
+INSERT INTO album_search (rowid, title, artist)
+SELECT rowid, title, artist FROM album;
+
+INSERT INTO artist_search (rowid, name)
+SELECT rowid, name FROM artist;
+
+CREATE TRIGGER track_search_insert AFTER INSERT ON track
+BEGIN
+    INSERT INTO track_search (rowid, title, artist, song_artist)
+    VALUES (NEW.rowid, NEW.title, NEW.artist, NEW.song_artist);
+END;
+
+CREATE TRIGGER track_search_update AFTER UPDATE OF title, artist, song_artist ON track
+BEGIN
+    DELETE FROM track_search WHERE rowid = OLD.rowid;
+    INSERT INTO track_search (rowid, title, artist, song_artist)
+    VALUES (NEW.rowid, NEW.title, NEW.artist, NEW.song_artist);
+END;
+
+CREATE TRIGGER track_search_delete AFTER DELETE ON track
+BEGIN
+    DELETE FROM track_search WHERE rowid = OLD.rowid;
+END;
+
+CREATE TRIGGER album_search_insert AFTER INSERT ON album
+BEGIN
+    INSERT INTO album_search (rowid, title, artist)
+    VALUES (NEW.rowid, NEW.title, NEW.artist);
+END;
+
+CREATE TRIGGER album_search_update AFTER UPDATE OF title, artist ON album
+BEGIN
+    DELETE FROM album_search WHERE rowid = OLD.rowid;
+    INSERT INTO album_search (rowid, title, artist)
+    VALUES (NEW.rowid, NEW.title, NEW.artist);
+END;
+
+CREATE TRIGGER album_search_delete AFTER DELETE ON album
+BEGIN
+    DELETE FROM album_search WHERE rowid = OLD.rowid;
+END;
+
+CREATE TRIGGER artist_search_insert AFTER INSERT ON artist
+BEGIN
+    INSERT INTO artist_search (rowid, name) VALUES (NEW.rowid, NEW.name);
+END;
+
+CREATE TRIGGER artist_search_delete AFTER DELETE ON artist
+BEGIN
+    DELETE FROM artist_search WHERE rowid = OLD.rowid;
+END;
@@ -0,0 +1,21 @@
+-- Albums can be sorted by when they were added and how much they've been
+-- played, both worked out from their tracks
+DROP VIEW album_summary;
+
+CREATE VIEW album_summary AS
+SELECT
+    album.*,
+    COUNT(track.id) AS track_count,
+    COALESCE(SUM(track.duration), 0) AS total_duration,
+    -- Tracks without a disc number count as being on the first disc
+    COUNT(DISTINCT CASE WHEN track.id IS NOT NULL THEN COALESCE(track.cd_number, 1) END) AS disc_count,
+    MIN(track.year) AS first_year,
+    MAX(track.year) AS last_year,
+    MIN(track.added_at) AS added_at,
+    COALESCE(SUM(track.play_count), 0) AS play_count,
+    MAX(track.last_played) AS last_played
+FROM album
+LEFT JOIN track ON track.album_id = album.id
+GROUP BY album.id;
+
+CREATE INDEX track_album ON track (album_id);
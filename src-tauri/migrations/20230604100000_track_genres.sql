@@ -0,0 +1,18 @@
+-- Tracks can have any number of genres. `track.genre` stays as the first of
+-- them.
+CREATE TABLE track_genre (
+    track_id TEXT NOT NULL REFERENCES track,
+    genre TEXT NOT NULL REFERENCES genre,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, position),
+    UNIQUE (track_id, genre)
+);
+
+CREATE INDEX track_genre_genre ON track_genre (genre);
+
+INSERT INTO track_genre (track_id, genre, position)
+SELECT id, genre, 0 FROM track WHERE genre IS NOT NULL;
+
+-- Genres used to be read as a single value, so make the next scan read every
+-- file's tags again to split them up
+UPDATE track SET modified = 0;
@@ -0,0 +1,22 @@
+-- Everyone credited on a track, so artists who only appear on some tracks,
+-- or only as a composer or featured artist, can still be found
+CREATE TABLE artist_credit (
+    track_id TEXT NOT NULL REFERENCES track,
+    artist TEXT NOT NULL REFERENCES artist,
+    -- One of main, featured, composer, conductor or remixer
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, position),
+    UNIQUE (track_id, role, artist)
+);
+
+CREATE INDEX artist_credit_artist ON artist_credit (artist);
+
+INSERT OR IGNORE INTO artist (name)
+SELECT DISTINCT song_artist FROM track WHERE song_artist IS NOT NULL;
+
+INSERT INTO artist_credit (track_id, artist, role, position)
+SELECT id, COALESCE(song_artist, artist), 'main', 0 FROM track;
+
+-- Credits are read from tags, so make the next scan read every file again
+UPDATE track SET modified = 0;
@@ -0,0 +1,13 @@
+-- Cover images from album folders are now copied into the artwork cache, as
+-- the artwork protocol only serves files from there. Albums pointing at the
+-- originals pick up a cached copy when their tracks are next scanned.
+UPDATE album SET artwork_path = NULL
+WHERE artwork_path GLOB '*[/\]cover.png'
+    OR artwork_path GLOB '*[/\]artwork.png'
+    OR artwork_path GLOB '*[/\]folder.png'
+    OR artwork_path GLOB '*[/\]cover.jpg'
+    OR artwork_path GLOB '*[/\]artwork.jpg'
+    OR artwork_path GLOB '*[/\]folder.jpg';
+
+UPDATE track SET modified = 0
+WHERE album_id IN (SELECT id FROM album WHERE artwork_path IS NULL);
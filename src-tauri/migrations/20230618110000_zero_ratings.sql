@@ -0,0 +1,2 @@
+-- A rating of 0 stars now means unrated, as it already did in ID3v2 tags
+UPDATE track SET rating = NULL WHERE rating = 0;
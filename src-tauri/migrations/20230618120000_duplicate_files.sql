@@ -0,0 +1,10 @@
+-- Files left out of the library because their audio is already in it as
+-- another track, so scans only read them again once they've changed
+CREATE TABLE duplicate_file (
+    path TEXT NOT NULL PRIMARY KEY,
+    track_id TEXT NOT NULL REFERENCES track,
+    file_size INTEGER NOT NULL,
+    modified INTEGER NOT NULL
+);
+
+CREATE INDEX duplicate_file_track ON duplicate_file (track_id);
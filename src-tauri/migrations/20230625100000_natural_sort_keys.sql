@@ -0,0 +1,15 @@
+-- Names are sorted by keys worked out when they're saved, so pages can be
+-- read in order from an index rather than sorted with the natural collation
+-- every time. Left NULL here and filled in when the library is opened.
+ALTER TABLE track ADD COLUMN sort_title TEXT;
+ALTER TABLE track ADD COLUMN sort_artist TEXT;
+ALTER TABLE track ADD COLUMN sort_album TEXT;
+ALTER TABLE album ADD COLUMN sort_title TEXT;
+ALTER TABLE album ADD COLUMN sort_artist TEXT;
+ALTER TABLE artist ADD COLUMN sort_name TEXT;
+
+CREATE INDEX track_sort_artist ON track (sort_artist, sort_album, cd_number, track_number, id);
+CREATE INDEX track_sort_title ON track (sort_title, id);
+CREATE INDEX album_sort_artist ON album (sort_artist, year, sort_title, id);
+CREATE INDEX album_sort_title ON album (sort_title, id);
+CREATE INDEX artist_sort_name ON artist (sort_name, name);
@@ -0,0 +1,115 @@
+-- The search indexes shared their rowids with the tables they index, but
+-- those tables have text primary keys, so VACUUM is free to renumber their
+-- rowids and leave the indexes pointing at the wrong rows. Each indexed row
+-- now gets a key of its own, which VACUUM keeps as it's an INTEGER PRIMARY
+-- KEY, and the indexes are rebuilt on those.
+DROP TRIGGER track_search_insert;
+DROP TRIGGER track_search_update;
+DROP TRIGGER track_search_delete;
+DROP TRIGGER album_search_insert;
+DROP TRIGGER album_search_update;
+DROP TRIGGER album_search_delete;
+DROP TRIGGER artist_search_insert;
+DROP TRIGGER artist_search_delete;
+DROP TABLE track_search;
+DROP TABLE album_search;
+DROP TABLE artist_search;
+
+CREATE TABLE track_search_key (
+    key INTEGER PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE album_search_key (
+    key INTEGER PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE artist_search_key (
+    key INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE VIRTUAL TABLE track_search USING fts5 (
+    title,
+    artist,
+    song_artist,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE album_search USING fts5 (
+    title,
+    artist,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE artist_search USING fts5 (
+    name,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO track_search_key (id) SELECT id FROM track;
+INSERT INTO album_search_key (id) SELECT id FROM album;
+INSERT INTO artist_search_key (name) SELECT name FROM artist;
+
+INSERT INTO track_search (rowid, title, artist, song_artist)
+SELECT key, title, artist, song_artist FROM track JOIN track_search_key USING (id);
+
+INSERT INTO album_search (rowid, title, artist)
+SELECT key, title, artist FROM album JOIN album_search_key USING (id);
+
+INSERT INTO artist_search (rowid, name)
+SELECT key, name FROM artist JOIN artist_search_key USING (name);
+
+CREATE TRIGGER track_search_insert AFTER INSERT ON track
+BEGIN
+    INSERT INTO track_search_key (id) VALUES (NEW.id);
+    INSERT INTO track_search (rowid, title, artist, song_artist)
+    SELECT key, NEW.title, NEW.artist, NEW.song_artist FROM track_search_key WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER track_search_update AFTER UPDATE OF title, artist, song_artist ON track
+BEGIN
+    DELETE FROM track_search WHERE rowid = (SELECT key FROM track_search_key WHERE id = OLD.id);
+    INSERT INTO track_search (rowid, title, artist, song_artist)
+    SELECT key, NEW.title, NEW.artist, NEW.song_artist FROM track_search_key WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER track_search_delete AFTER DELETE ON track
+BEGIN
+    DELETE FROM track_search WHERE rowid = (SELECT key FROM track_search_key WHERE id = OLD.id);
+    DELETE FROM track_search_key WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER album_search_insert AFTER INSERT ON album
+BEGIN
+    INSERT INTO album_search_key (id) VALUES (NEW.id);
+    INSERT INTO album_search (rowid, title, artist)
+    SELECT key, NEW.title, NEW.artist FROM album_search_key WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER album_search_update AFTER UPDATE OF title, artist ON album
+BEGIN
+    DELETE FROM album_search WHERE rowid = (SELECT key FROM album_search_key WHERE id = OLD.id);
+    INSERT INTO album_search (rowid, title, artist)
+    SELECT key, NEW.title, NEW.artist FROM album_search_key WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER album_search_delete AFTER DELETE ON album
+BEGIN
+    DELETE FROM album_search WHERE rowid = (SELECT key FROM album_search_key WHERE id = OLD.id);
+    DELETE FROM album_search_key WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER artist_search_insert AFTER INSERT ON artist
+BEGIN
+    INSERT INTO artist_search_key (name) VALUES (NEW.name);
+    INSERT INTO artist_search (rowid, name)
+    SELECT key, NEW.name FROM artist_search_key WHERE name = NEW.name;
+END;
+
+CREATE TRIGGER artist_search_delete AFTER DELETE ON artist
+BEGIN
+    DELETE FROM artist_search WHERE rowid = (SELECT key FROM artist_search_key WHERE name = OLD.name);
+    DELETE FROM artist_search_key WHERE name = OLD.name;
+END;
@@ -0,0 +1,483 @@
+//! Cover art: caching what's embedded in tracks or kept in their folders, serving it to the frontend
+//! and editing it.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageOutputFormat};
+use lofty::{AudioFile, MimeType, Picture, PictureType};
+use tauri::http::{Request, Response, ResponseBuilder};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::library::ScanJob;
+use crate::models::MetadataEditResult;
+use crate::store::Store;
+use crate::tags::{edit_tracks, primary_tag_mut};
+use crate::{create_cache_dir, Error, Result};
+
+/// Cached artwork is served as `artwork://localhost/<file name>`, with an
+/// optional `?size=` of one of the thumbnail sizes
+pub const ARTWORK_PROTOCOL: &str = "artwork";
+
+/// Names of the thumbnail sizes the frontend can ask for, with how many
+/// pixels wide and tall they fit in
+const THUMBNAIL_SIZES: [(&str, u32); 2] = [("list", 96), ("grid", 300)];
+
+const THUMBNAIL_DIR: &str = "thumbnails";
+
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Covers wider or taller than this are scaled down before being embedded
+const MAX_COVER_SIZE: u32 = 1200;
+
+/// Covers bigger than this are re-encoded as JPEG, even if they're small
+/// enough in pixels
+const MAX_COVER_BYTES: usize = 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Images in an album's folder that are used as its cover, in order of
+/// preference
+const FOLDER_ARTWORK: [&str; 6] = [
+    "cover.png",
+    "artwork.png",
+    "folder.png",
+    "cover.jpg",
+    "artwork.jpg",
+    "folder.jpg",
+];
+
+/// Saves a picture to the cache, named after a hash of its contents so that
+/// tracks with the same art share a file. Returns where it was saved.
+pub fn cache_picture(cache_dir: &Path, picture: &Picture) -> Result<PathBuf> {
+    let ext = match picture.mime_type() {
+        MimeType::Jpeg => Some("jpg"),
+        MimeType::Png => Some("png"),
+        MimeType::Gif => Some("gif"),
+        MimeType::Bmp => Some("bmp"),
+        MimeType::Tiff => Some("tiff"),
+        _ => None,
+    };
+    cache_image(cache_dir, picture.data(), ext)
+}
+
+/// Copies the cover image in `dir`, if there is one, into the cache.
+pub fn cache_folder_artwork(cache_dir: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+    for name in FOLDER_ARTWORK {
+        let file = dir.join(name);
+        if file.is_file() {
+            let data = fs::read(&file)?;
+            return cache_image(cache_dir, &data, None).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Saves image data to the cache under a hash of its contents, making its
+/// thumbnails straight away so they're ready by the time they're asked for.
+fn cache_image(cache_dir: &Path, data: &[u8], mime_ext: Option<&'static str>) -> Result<PathBuf> {
+    let hash = md5::compute(data);
+    let ext = image::guess_format(data)
+        .ok()
+        .and_then(|f| f.extensions_str().first().copied())
+        .or(mime_ext)
+        .unwrap_or("img");
+
+    let path = cache_dir.join(format!("{:x}.{}", hash, ext));
+    if !path.exists() {
+        // Written elsewhere and renamed into place, so the file is never
+        // seen half written
+        let partial = path.with_extension("partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, &path)?;
+        // Art that can't be decoded is still served, just at full size
+        if let Err(e) = make_thumbnails(cache_dir, &path) {
+            log::warn!("failed to make thumbnails of {}: {}", path.display(), e);
+        }
+    }
+    Ok(path)
+}
+
+/// Remembers the cover image found in each folder during a scan, so it's
+/// only read once per album rather than once per track.
+#[derive(Default)]
+pub struct FolderArtwork(Mutex<HashMap<PathBuf, Option<PathBuf>>>);
+
+impl FolderArtwork {
+    /// Returns the cached copy of the cover image in `dir`. A cover that
+    /// can't be read is logged and treated as missing, rather than failing
+    /// every track in the folder.
+    pub fn get(&self, cache_dir: &Path, dir: &Path) -> Option<PathBuf> {
+        if let Some(found) = self.0.lock().unwrap().get(dir) {
+            return found.clone();
+        }
+        let found = cache_folder_artwork(cache_dir, dir).unwrap_or_else(|e| {
+            log::warn!("failed to read cover art in {}: {}", dir.display(), e);
+            None
+        });
+        self.0.lock().unwrap().insert(dir.into(), found.clone());
+        found
+    }
+}
+
+/// Artwork whose thumbnails are being made in the background, so that each
+/// is only made once however many times it's asked for meanwhile.
+#[derive(Default)]
+pub struct PendingThumbnails(Mutex<HashSet<PathBuf>>);
+
+/// Serves `artwork://` requests from the cache. Thumbnails are made when art
+/// is cached, but art cached before then gets them made in the background,
+/// with the full size image served until they're ready.
+pub fn handle_request<R: Runtime>(
+    app: &AppHandle<R>,
+    request: &Request,
+) -> std::result::Result<Response, Box<dyn std::error::Error>> {
+    let (path, query) = request.uri().split_once('?').unwrap_or((request.uri(), ""));
+    let name = path.rsplit('/').next().unwrap_or_default();
+    // Only ever files directly in the cache
+    let valid_name = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_');
+    if !valid_name {
+        return not_found();
+    }
+
+    let cache_dir = create_cache_dir()?;
+    let original = cache_dir.join(name);
+    if !original.is_file() {
+        return not_found();
+    }
+    let size = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("size="))
+        .and_then(|size| THUMBNAIL_SIZES.iter().find(|(s, _)| *s == size));
+    let (file, cache_control) = match size {
+        Some((size, _)) => {
+            let thumbnail = thumbnail_path(&cache_dir, &original, size);
+            if thumbnail.is_file() {
+                (thumbnail, IMMUTABLE)
+            } else {
+                make_thumbnails_later(app, cache_dir, original.clone());
+                // Asked for again once the thumbnail is there
+                (original, "no-cache")
+            }
+        }
+        None => (original, IMMUTABLE),
+    };
+
+    let data = fs::read(&file)?;
+    let mime_type = image::guess_format(&data)
+        .map(mime_type)
+        .unwrap_or("application/octet-stream");
+    ResponseBuilder::new()
+        .mimetype(mime_type)
+        .header("Cache-Control", cache_control)
+        .status(200)
+        .body(data)
+}
+
+/// Files are named after their contents, so never change
+const IMMUTABLE: &str = "max-age=31536000, immutable";
+
+fn make_thumbnails_later<R: Runtime>(app: &AppHandle<R>, cache_dir: PathBuf, original: PathBuf) {
+    let pending = app.state::<PendingThumbnails>();
+    if !pending.0.lock().unwrap().insert(original.clone()) {
+        return;
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(e) = make_thumbnails(&cache_dir, &original) {
+            log::warn!("failed to make thumbnails of {}: {}", original.display(), e);
+        }
+        app.state::<PendingThumbnails>()
+            .0
+            .lock()
+            .unwrap()
+            .remove(&original);
+    });
+}
+
+fn not_found() -> std::result::Result<Response, Box<dyn std::error::Error>> {
+    ResponseBuilder::new().status(404).body(vec![])
+}
+
+fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::Tiff => "image/tiff",
+        _ => "application/octet-stream",
+    }
+}
+
+fn thumbnail_path(cache_dir: &Path, original: &Path, size: &str) -> PathBuf {
+    let stem = original.file_stem().unwrap_or_default().to_string_lossy();
+    cache_dir
+        .join(THUMBNAIL_DIR)
+        .join(size)
+        .join(format!("{}.jpg", stem))
+}
+
+/// Makes each size of thumbnail of `original` that doesn't exist yet.
+fn make_thumbnails(cache_dir: &Path, original: &Path) -> Result<()> {
+    let missing: Vec<_> = THUMBNAIL_SIZES
+        .iter()
+        .map(|(size, pixels)| (thumbnail_path(cache_dir, original, size), *pixels))
+        .filter(|(path, _)| !path.exists())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let image = image::open(original)?;
+    for (path, pixels) in missing {
+        let thumbnail = if image.width() > pixels || image.height() > pixels {
+            image.thumbnail(pixels, pixels).into_rgb8()
+        } else {
+            image.to_rgb8()
+        };
+        let thumbnail = image::DynamicImage::ImageRgb8(thumbnail);
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        let partial = path.with_extension("partial");
+        let mut file = BufWriter::new(File::create(&partial)?);
+        thumbnail.write_to(&mut file, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))?;
+        drop(file);
+        fs::rename(&partial, &path)?;
+    }
+    Ok(())
+}
+
+/// Removes thumbnails of artwork that's no longer in the cache, returning
+/// how many were removed.
+pub fn remove_orphaned_thumbnails(cache_dir: &Path) -> Result<u64> {
+    let mut originals = HashSet::new();
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            originals.extend(path.file_stem().map(|s| s.to_os_string()));
+        }
+    }
+
+    let mut removed = 0;
+    for (size, _) in THUMBNAIL_SIZES {
+        let dir = cache_dir.join(THUMBNAIL_DIR).join(size);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let has_original = path
+                .file_stem()
+                .map_or(false, |stem| originals.contains(stem));
+            if !has_original {
+                match fs::remove_file(&path) {
+                    Ok(()) => removed += 1,
+                    Err(e) => log::warn!("failed to remove {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Embeds the image at `image_path` as the front cover of each track.
+#[tauri::command]
+pub async fn set_track_artwork(
+    window: tauri::Window,
+    store: tauri::State<'_, Store>,
+    track_ids: Vec<String>,
+    image_path: PathBuf,
+) -> Result<MetadataEditResult> {
+    let cover = tauri::async_runtime::spawn_blocking(move || load_cover(&image_path)).await??;
+    let job = window.state::<ScanJob>();
+    let guard = job.lock().await;
+    let result = edit_tracks(&guard, &window, &store, &track_ids, move |path| {
+        embed_cover(path, cover.clone())
+    })
+    .await?;
+    // Still holding the lock, so a scan can't replace the artwork meanwhile
+    refresh_albums(&store, &track_ids).await?;
+    Ok(result)
+}
+
+/// Removes all the pictures embedded in each track.
+#[tauri::command]
+pub async fn remove_track_artwork(
+    window: tauri::Window,
+    store: tauri::State<'_, Store>,
+    track_ids: Vec<String>,
+) -> Result<MetadataEditResult> {
+    let job = window.state::<ScanJob>();
+    let guard = job.lock().await;
+    let result = edit_tracks(&guard, &window, &store, &track_ids, remove_pictures).await?;
+    refresh_albums(&store, &track_ids).await?;
+    Ok(result)
+}
+
+/// Embeds the image at `image_path` as the front cover of every track on an
+/// album.
+#[tauri::command]
+pub async fn set_album_artwork(
+    window: tauri::Window,
+    store: tauri::State<'_, Store>,
+    album_id: String,
+    image_path: PathBuf,
+) -> Result<MetadataEditResult> {
+    let track_ids = store.get_album_track_ids(&album_id).await?;
+    if track_ids.is_empty() {
+        return Err(Error::NotFound("album", album_id));
+    }
+    set_track_artwork(window, store, track_ids, image_path).await
+}
+
+/// Albums take their artwork from their folder or else their tracks, so it
+/// needs updating once the tracks' artwork has changed.
+async fn refresh_albums(store: &Store, track_ids: &[String]) -> Result<()> {
+    let mut album_dirs = HashMap::new();
+    for id in track_ids {
+        if let Some(file) = store.get_track_file(id).await? {
+            let dir = file.path.parent().map(Path::to_path_buf);
+            album_dirs.insert(file.album_id, dir);
+        }
+    }
+    let cache_dir = create_cache_dir()?;
+    let folder_artwork: HashMap<_, _> = tauri::async_runtime::spawn_blocking(move || {
+        let folders = FolderArtwork::default();
+        album_dirs
+            .into_iter()
+            .map(|(id, dir)| {
+                let artwork = dir.and_then(|dir| folders.get(&cache_dir, &dir));
+                (id, artwork)
+            })
+            .collect()
+    })
+    .await?;
+    store.refresh_album_artwork(&folder_artwork).await
+}
+
+/// Reads an image to embed, scaling it down and re-encoding it as a JPEG if
+/// it's too big or in a format that tags can't hold.
+fn load_cover(path: &Path) -> Result<Picture> {
+    let data = fs::read(path)?;
+    let format = image::guess_format(&data)?;
+    let mime_type = match format {
+        ImageFormat::Jpeg => Some(MimeType::Jpeg),
+        ImageFormat::Png => Some(MimeType::Png),
+        _ => None,
+    };
+
+    let image = image::load_from_memory_with_format(&data, format)?;
+    let too_big = image.width() > MAX_COVER_SIZE
+        || image.height() > MAX_COVER_SIZE
+        || data.len() > MAX_COVER_BYTES;
+    let picture = match mime_type {
+        Some(mime_type) if !too_big => {
+            Picture::new_unchecked(PictureType::CoverFront, mime_type, None, data)
+        }
+        _ => {
+            let image = if image.width() > MAX_COVER_SIZE || image.height() > MAX_COVER_SIZE {
+                image.resize(MAX_COVER_SIZE, MAX_COVER_SIZE, FilterType::Lanczos3)
+            } else {
+                image
+            };
+            // JPEG has no alpha channel
+            let image = image::DynamicImage::ImageRgb8(image.into_rgb8());
+            let mut jpeg = Cursor::new(vec![]);
+            image.write_to(&mut jpeg, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+            Picture::new_unchecked(
+                PictureType::CoverFront,
+                MimeType::Jpeg,
+                None,
+                jpeg.into_inner(),
+            )
+        }
+    };
+    Ok(picture)
+}
+
+/// Replaces the front cover in the file's main tag with `cover`.
+fn embed_cover(path: &Path, cover: Picture) -> Result<()> {
+    let mut file = lofty::read_from_path(path)?;
+    let tag = primary_tag_mut(&mut file);
+    tag.remove_picture_type(PictureType::CoverFront);
+    // Tracks without a front cover have their art read from here instead
+    tag.remove_picture_type(PictureType::Other);
+    tag.push_picture(cover);
+    file.save_to_path(path)?;
+    Ok(())
+}
+
+fn remove_pictures(path: &Path) -> Result<()> {
+    let mut file = lofty::read_from_path(path)?;
+    let tag = primary_tag_mut(&mut file);
+    let mut types = vec![];
+    for picture in tag.pictures() {
+        if !types.contains(&picture.pic_type()) {
+            types.push(picture.pic_type());
+        }
+    }
+    for pic_type in types {
+        tag.remove_picture_type(pic_type);
+    }
+    file.save_to_path(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tome-artwork-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_png(path: &Path, colour: [u8; 3]) {
+        let image = image::RgbImage::from_pixel(400, 400, image::Rgb(colour));
+        image.save_with_format(path, ImageFormat::Png).unwrap();
+    }
+
+    #[test]
+    fn folder_covers_with_the_same_name_are_cached_apart() {
+        let root = temp_dir("folders");
+        let cache_dir = root.join("cache");
+        fs::create_dir(&cache_dir).unwrap();
+        for (album, colour) in [("a", [255, 0, 0]), ("b", [0, 0, 255])] {
+            fs::create_dir(root.join(album)).unwrap();
+            write_png(&root.join(album).join("cover.png"), colour);
+        }
+
+        let a = cache_folder_artwork(&cache_dir, &root.join("a")).unwrap();
+        let b = cache_folder_artwork(&cache_dir, &root.join("b")).unwrap();
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_ne!(a, b);
+        for path in [&a, &b] {
+            assert_eq!(path.parent(), Some(cache_dir.as_path()));
+            for (size, _) in THUMBNAIL_SIZES {
+                assert!(thumbnail_path(&cache_dir, path, size).is_file());
+            }
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn folders_without_a_cover_have_no_artwork() {
+        let root = temp_dir("empty");
+        assert_eq!(cache_folder_artwork(&root, &root).unwrap(), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
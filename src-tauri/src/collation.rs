@@ -0,0 +1,175 @@
+//! The `natural` collation used to sort names, so that "Track 2" comes before
+//! "Track 10" and "Émilie" sorts with the other Es, and the sort keys that
+//! put names in the same order when stored in the library.
+
+use std::cmp::Ordering;
+use std::iter::{self, Peekable};
+use std::str::Chars;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Name of the collation, for `COLLATE natural`
+pub const NATURAL: &str = "natural";
+
+/// Compares runs of digits by their value and everything else ignoring case
+/// and accents. Strings that only differ in those are then ordered as they
+/// are, so the order is still total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let ord = cmp_numbers(&mut a_chars, &mut b_chars);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(&x), Some(&y)) => {
+                a_chars.next();
+                b_chars.next();
+                let ord = fold(x).cmp(fold(y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+        }
+    }
+}
+
+/// Returns a key that sorts with plain string comparison the way
+/// `natural_cmp` sorts `s`: lowercased without accents, with each run of
+/// digits written as its length and then its digits. Strings that only
+/// differ in case, accents or leading zeros get the same key.
+pub fn natural_key(s: &str) -> String {
+    let mut key = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            let digits: String = iter::once(c)
+                .chain(iter::from_fn(|| chars.next_if(char::is_ascii_digit)))
+                .skip_while(|&d| d == '0')
+                .collect();
+            // Numbers of more than 99 digits aren't worth a longer key
+            key.push_str(&format!("{:02}", digits.len().min(99)));
+            key.push_str(&digits);
+        } else {
+            key.extend(fold(c));
+        }
+    }
+    key
+}
+
+/// Compares the runs of digits at the start of `a` and `b` by their value,
+/// consuming them.
+fn cmp_numbers(a: &mut Peekable<Chars>, b: &mut Peekable<Chars>) -> Ordering {
+    while a.next_if_eq(&'0').is_some() {}
+    while b.next_if_eq(&'0').is_some() {}
+    // Without leading zeros the longer number is the larger one, and numbers
+    // as long as each other differ where their digits first do
+    let mut ord = Ordering::Equal;
+    loop {
+        match (
+            a.next_if(char::is_ascii_digit),
+            b.next_if(char::is_ascii_digit),
+        ) {
+            (Some(x), Some(y)) => ord = ord.then(x.cmp(&y)),
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return ord,
+        }
+    }
+}
+
+/// Lowercases a character and takes its accents off.
+fn fold(c: char) -> impl Iterator<Item = char> {
+    iter::once(c)
+        .nfd()
+        .filter(|&d| !is_combining_mark(d))
+        .flat_map(char::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names in the order they should sort in, none equal to another.
+    const SORTED: &[&str] = &[
+        "",
+        "1 Giant Leap",
+        "2 Unlimited",
+        "10cc",
+        "101 Strings",
+        "ABBA",
+        "Abba Teens",
+        "Émilie Simon",
+        "Erasure",
+        "Track 2",
+        "Track 02b",
+        "Track 10",
+        "Track 100000000000000000000",
+        "Zoë Keating",
+    ];
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(natural_cmp("Track 2", "Track 10"), Ordering::Less);
+        assert_eq!(natural_cmp("Track 10", "Track 9"), Ordering::Greater);
+        assert_eq!(natural_cmp("Track 19", "Track 20"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("Track 02", "Track 3"), Ordering::Less);
+        assert_eq!(natural_cmp("Track 002 b", "Track 2 a"), Ordering::Greater);
+        assert_ne!(natural_cmp("Track 02", "Track 2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn case_and_accents_only_break_ties() {
+        assert_eq!(natural_cmp("émilie", "Emily"), Ordering::Less);
+        assert_eq!(natural_cmp("abba", "ABBA Gold"), Ordering::Less);
+        assert_ne!(natural_cmp("Émilie", "Emilie"), Ordering::Equal);
+        assert_eq!(natural_cmp("Émilie", "Émilie"), Ordering::Equal);
+    }
+
+    #[test]
+    fn sorts_names_in_order() {
+        for (i, a) in SORTED.iter().enumerate() {
+            for (j, b) in SORTED.iter().enumerate() {
+                assert_eq!(natural_cmp(a, b), i.cmp(&j), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_sort_like_the_collation() {
+        for (i, a) in SORTED.iter().enumerate() {
+            for (j, b) in SORTED.iter().enumerate() {
+                assert_eq!(
+                    natural_key(a).cmp(&natural_key(b)),
+                    i.cmp(&j),
+                    "{:?} and {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keys_ignore_case_accents_and_leading_zeros() {
+        assert_eq!(natural_key("Émilie"), natural_key("emilie"));
+        assert_eq!(natural_key("Track 02"), natural_key("track 2"));
+        assert_eq!(natural_key("Track 0"), "track 00");
+        assert_eq!(natural_key("Track 10"), "track 0210");
+    }
+}
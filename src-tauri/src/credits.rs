@@ -0,0 +1,195 @@
+//! Working out who's credited on a track from its tags, where artists can be
+//! spread over several values or tucked into a "feat." in the artist or
+//! title.
+
+use lofty::{ItemKey, Tag};
+
+use crate::models::{ArtistCredit, ArtistRole};
+
+/// Words that introduce featured artists, in lowercase. Longer ones come
+/// first so "feat." isn't read as "feat".
+const FEATURING: [&str; 5] = ["featuring", "feat.", "feat", "ft.", "ft"];
+
+/// Reads everyone credited on a track. The main artists come from the track
+/// artist, or `fallback` if there isn't one, and anyone featured in it or in
+/// the title is credited as featured.
+pub fn read_credits(tag: &Tag, title: &str, fallback: Option<&str>) -> Vec<ArtistCredit> {
+    let mut credits = Credits::default();
+
+    let mut artists: Vec<&str> = tag.get_strings(&ItemKey::TrackArtist).collect();
+    if artists.is_empty() {
+        artists.extend(fallback);
+    }
+    for value in artists {
+        for artist in split_values(value) {
+            let (main, featured) = split_featuring(artist);
+            credits.add(main, ArtistRole::Main);
+            for name in featured.map(split_names).unwrap_or_default() {
+                credits.add(name, ArtistRole::Featured);
+            }
+        }
+    }
+    if let (_, Some(featured)) = split_featuring(title) {
+        for name in split_names(featured) {
+            credits.add(name, ArtistRole::Featured);
+        }
+    }
+
+    let roles = [
+        (ItemKey::Composer, ArtistRole::Composer),
+        (ItemKey::Conductor, ArtistRole::Conductor),
+        (ItemKey::Remixer, ArtistRole::Remixer),
+    ];
+    for (key, role) in roles {
+        for value in tag.get_strings(&key) {
+            for name in split_values(value) {
+                credits.add(name, role);
+            }
+        }
+    }
+    credits.0
+}
+
+#[derive(Default)]
+struct Credits(Vec<ArtistCredit>);
+
+impl Credits {
+    fn add(&mut self, name: &str, role: ArtistRole) {
+        let name = name.trim();
+        let exists = self
+            .0
+            .iter()
+            .any(|c| c.role == role && c.name.eq_ignore_ascii_case(name));
+        if !name.is_empty() && !exists {
+            self.0.push(ArtistCredit {
+                name: name.into(),
+                role,
+            });
+        }
+    }
+}
+
+/// Splits a tag value holding several artists. `&` and commas are left
+/// alone here, as plenty of artists have them in their names.
+fn split_values(value: &str) -> Vec<&str> {
+    value
+        .split([';', '\0'])
+        .flat_map(|v| v.split(" / "))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Splits a list of featured artists, such as "A, B & C".
+fn split_names(names: &str) -> Vec<&str> {
+    names
+        .split([',', '&', ';'])
+        .flat_map(|n| n.split(" and "))
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// Splits "A feat. B" or "Title (feat. B)" into what comes before the
+/// featured artists and the artists themselves.
+fn split_featuring(s: &str) -> (&str, Option<&str>) {
+    // Only ASCII is lowercased so that positions in it are the same as in `s`
+    let lower = s.to_ascii_lowercase();
+    for (i, opener) in lower.match_indices([' ', '(', '[']) {
+        let start = i + 1;
+        let marker = FEATURING
+            .iter()
+            .find(|m| lower[start..].starts_with(*m) && lower[start + m.len()..].starts_with(' '));
+        let marker = match marker {
+            Some(m) => m,
+            None => continue,
+        };
+
+        let rest = &s[start + marker.len()..];
+        let featured = match opener {
+            "(" => rest.split(')').next().unwrap_or(rest),
+            "[" => rest.split(']').next().unwrap_or(rest),
+            _ => rest,
+        };
+        return (s[..i].trim(), Some(featured.trim()));
+    }
+    (s.trim(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use lofty::TagType;
+
+    use super::*;
+
+    fn credit(name: &str, role: ArtistRole) -> ArtistCredit {
+        ArtistCredit {
+            name: name.into(),
+            role,
+        }
+    }
+
+    #[test]
+    fn finds_featured_artists() {
+        assert_eq!(split_featuring("A feat. B"), ("A", Some("B")));
+        assert_eq!(split_featuring("A Featuring B"), ("A", Some("B")));
+        assert_eq!(split_featuring("A ft B"), ("A", Some("B")));
+        assert_eq!(
+            split_featuring("Song (feat. B & C)"),
+            ("Song", Some("B & C"))
+        );
+        assert_eq!(split_featuring("Song [ft. B] (Remix)"), ("Song", Some("B")));
+        assert_eq!(split_featuring("Björk feat. B"), ("Björk", Some("B")));
+    }
+
+    #[test]
+    fn leaves_words_that_only_start_like_featuring() {
+        assert_eq!(split_featuring("Feathers"), ("Feathers", None));
+        assert_eq!(
+            split_featuring("The Featherweights"),
+            ("The Featherweights", None)
+        );
+        assert_eq!(split_featuring("Song feat"), ("Song feat", None));
+    }
+
+    #[test]
+    fn splits_values_and_names() {
+        assert_eq!(split_values("A; B / C\0D;"), ["A", "B", "C", "D"]);
+        assert_eq!(split_values("AC/DC"), ["AC/DC"]);
+        assert_eq!(split_values("Simon & Garfunkel"), ["Simon & Garfunkel"]);
+        assert_eq!(split_names("A, B & C and D"), ["A", "B", "C", "D"]);
+    }
+
+    #[test]
+    fn reads_every_role() {
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.insert_text(ItemKey::TrackArtist, "Main feat. Guest".into());
+        tag.insert_text(ItemKey::Composer, "Writer A; Writer B".into());
+        tag.insert_text(ItemKey::Conductor, "Conductor".into());
+        tag.insert_text(ItemKey::Remixer, "Remixer".into());
+
+        let credits = read_credits(&tag, "Song (ft. Other & guest)", Some("Album Artist"));
+        assert_eq!(
+            credits,
+            [
+                credit("Main", ArtistRole::Main),
+                credit("Guest", ArtistRole::Featured),
+                credit("Other", ArtistRole::Featured),
+                credit("Writer A", ArtistRole::Composer),
+                credit("Writer B", ArtistRole::Composer),
+                credit("Conductor", ArtistRole::Conductor),
+                credit("Remixer", ArtistRole::Remixer),
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_album_artist() {
+        let tag = Tag::new(TagType::Id3v2);
+        assert_eq!(
+            read_credits(&tag, "Song", Some("Album Artist")),
+            [credit("Album Artist", ArtistRole::Main)]
+        );
+        assert!(read_credits(&tag, "Song", None).is_empty());
+    }
+}
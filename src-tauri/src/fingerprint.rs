@@ -0,0 +1,466 @@
+//! Hashes of the audio stored in a file, leaving out any tags, so a track
+//! keeps the same identity when its tags are edited or the file is moved.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::Result;
+
+const ID3V1_SIZE: u64 = 128;
+const APE_FOOTER_SIZE: u64 = 32;
+
+/// Returns an md5 hash of the audio stream in the file at `path`. For
+/// formats that aren't understood, or that have no audio where it's
+/// expected, the whole file is hashed instead.
+pub fn audio_hash(path: &Path) -> Result<String> {
+    let mut file = BufReader::new(File::open(path)?);
+    let len = file.get_ref().metadata()?.len();
+    let start = skip_id3v2(&mut file)?;
+
+    let mut magic = [0; 12];
+    file.seek(SeekFrom::Start(start))?;
+    read_up_to(&mut file, &mut magic)?;
+
+    let mut hash = md5::Context::new();
+    let hashed = if magic.starts_with(b"fLaC") {
+        hash_flac(&mut file, start, len, &mut hash)?
+    } else if magic.starts_with(b"OggS") {
+        hash_ogg(&mut file, start, len, &mut hash)?
+    } else if magic.starts_with(b"RIFF") && &magic[8..] == b"WAVE" {
+        hash_chunks(&mut file, start, len, Endian::Little, b"data", &mut hash)?
+    } else if magic.starts_with(b"FORM") {
+        hash_chunks(&mut file, start, len, Endian::Big, b"SSND", &mut hash)?
+    } else if &magic[4..8] == b"ftyp" {
+        hash_mp4(&mut file, start, len, &mut hash)?
+    } else {
+        // Raw streams like MP3 and AAC, with tags only at either end
+        let end = tagless_end(&mut file, start, len)?;
+        hash_range(&mut file, start, end, &mut hash)?
+    };
+
+    if hashed == 0 {
+        hash = md5::Context::new();
+        hash_range(&mut file, 0, len, &mut hash)?;
+    }
+    Ok(format!("{:x}", hash.compute()))
+}
+
+enum Endian {
+    Little,
+    Big,
+}
+
+/// Returns where the data after any ID3v2 tag at the start of the file begins.
+fn skip_id3v2<R: Read + Seek>(file: &mut R) -> io::Result<u64> {
+    let mut header = [0; 10];
+    file.seek(SeekFrom::Start(0))?;
+    if read_up_to(file, &mut header)? < header.len() || !header.starts_with(b"ID3") {
+        return Ok(0);
+    }
+    // Sizes are synchsafe, using 7 bits from each byte
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, b| (size << 7) | (*b & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(10 + size + footer)
+}
+
+/// Returns where the data before any ID3v1 or APE tags at the end of the
+/// file ends.
+fn tagless_end<R: Read + Seek>(file: &mut R, start: u64, len: u64) -> io::Result<u64> {
+    let mut end = len;
+
+    if end >= start + ID3V1_SIZE {
+        let mut tag = [0; 3];
+        file.seek(SeekFrom::Start(end - ID3V1_SIZE))?;
+        file.read_exact(&mut tag)?;
+        if &tag == b"TAG" {
+            end -= ID3V1_SIZE;
+        }
+    }
+
+    if end >= start + APE_FOOTER_SIZE {
+        let mut footer = [0; APE_FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(end - APE_FOOTER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        if footer.starts_with(b"APETAGEX") {
+            // The size covers the items and footer, but not the header
+            let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+            let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+            let header = if flags & (1 << 31) != 0 {
+                APE_FOOTER_SIZE
+            } else {
+                0
+            };
+            end = end.saturating_sub(size + header);
+        }
+    }
+
+    Ok(end.max(start))
+}
+
+/// Hashes the frames following FLAC's metadata blocks, where the tags and
+/// pictures live.
+fn hash_flac<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    len: u64,
+    hash: &mut md5::Context,
+) -> io::Result<u64> {
+    let mut pos = start + 4;
+    loop {
+        let mut header = [0; 4];
+        file.seek(SeekFrom::Start(pos))?;
+        if read_up_to(file, &mut header)? < header.len() {
+            return Ok(0);
+        }
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        pos += 4 + size;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    let end = tagless_end(file, pos, len)?;
+    hash_range(file, pos, end, hash)
+}
+
+/// Hashes the bodies of the Ogg pages that follow the header packets, which
+/// hold the comments.
+fn hash_ogg<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    len: u64,
+    hash: &mut md5::Context,
+) -> io::Result<u64> {
+    let mut pos = start;
+    let mut in_audio = false;
+    let mut hashed = 0;
+    while pos + 27 <= len {
+        let mut header = [0; 27];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+        if !header.starts_with(b"OggS") {
+            break;
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let mut segments = vec![0; header[26] as usize];
+        file.read_exact(&mut segments)?;
+        let body_start = pos + 27 + segments.len() as u64;
+        let body_len: u64 = segments.iter().map(|s| *s as u64).sum();
+
+        // Header pages have a granule position of 0, and pages that don't
+        // finish a packet have -1, so the first page with a real position
+        // is where the audio starts
+        in_audio = in_audio || (granule != 0 && granule != u64::MAX);
+        if in_audio {
+            hashed += hash_range(file, body_start, (body_start + body_len).min(len), hash)?;
+        }
+        pos = body_start + body_len;
+    }
+    Ok(hashed)
+}
+
+/// Hashes the contents of the chunks named `id` in a RIFF or IFF file, such
+/// as WAV and AIFF.
+fn hash_chunks<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    len: u64,
+    endian: Endian,
+    id: &[u8; 4],
+    hash: &mut md5::Context,
+) -> io::Result<u64> {
+    let mut pos = start + 12;
+    let mut hashed = 0;
+    while pos + 8 <= len {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+        let size_bytes = header[4..8].try_into().unwrap();
+        let size = match endian {
+            Endian::Little => u32::from_le_bytes(size_bytes),
+            Endian::Big => u32::from_be_bytes(size_bytes),
+        } as u64;
+        if &header[..4] == id {
+            hashed += hash_range(file, pos + 8, (pos + 8 + size).min(len), hash)?;
+        }
+        // Chunks are padded to an even length
+        pos += 8 + size + (size & 1);
+    }
+    Ok(hashed)
+}
+
+/// Hashes the `mdat` atoms of an MP4 file, leaving out the metadata in
+/// `moov`.
+fn hash_mp4<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    len: u64,
+    hash: &mut md5::Context,
+) -> io::Result<u64> {
+    let mut pos = start;
+    let mut hashed = 0;
+    while pos + 8 <= len {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, len - pos),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                (16, u64::from_be_bytes(size))
+            }
+            size => (8, size as u64),
+        };
+        if size < header_len {
+            break;
+        }
+        if &header[4..] == b"mdat" {
+            hashed += hash_range(file, pos + header_len, (pos + size).min(len), hash)?;
+        }
+        pos += size;
+    }
+    Ok(hashed)
+}
+
+fn hash_range<R: Read + Seek>(
+    file: &mut R,
+    from: u64,
+    to: u64,
+    hash: &mut md5::Context,
+) -> io::Result<u64> {
+    if to <= from {
+        return Ok(0);
+    }
+    file.seek(SeekFrom::Start(from))?;
+    let mut reader = file.take(to - from);
+    let mut buf = [0; 64 * 1024];
+    let mut hashed = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hash.consume(&buf[..n]);
+        hashed += n as u64;
+    }
+    Ok(hashed)
+}
+
+/// Reads into `buf` until it's full or the end of the file is reached,
+/// returning how much was read.
+fn read_up_to<R: Read>(file: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("tome-fingerprint-{}", name));
+        std::fs::write(&path, bytes).unwrap();
+        let hash = audio_hash(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        hash
+    }
+
+    fn id3v2(frames: &[u8]) -> Vec<u8> {
+        let size = frames.len() as u32;
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        // Synchsafe, 7 bits to a byte
+        tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+        tag.extend_from_slice(frames);
+        tag
+    }
+
+    fn id3v1(title: &str) -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.extend_from_slice(title.as_bytes());
+        tag.resize(ID3V1_SIZE as usize, 0);
+        tag
+    }
+
+    fn ape(items: &[u8]) -> Vec<u8> {
+        let block = |flags: u32| {
+            let mut block = b"APETAGEX".to_vec();
+            block.extend_from_slice(&2000u32.to_le_bytes());
+            block.extend_from_slice(&(items.len() as u32 + 32).to_le_bytes());
+            block.extend_from_slice(&1u32.to_le_bytes());
+            block.extend_from_slice(&flags.to_le_bytes());
+            block.extend_from_slice(&[0; 8]);
+            block
+        };
+        let has_header = 1 << 31;
+        let mut tag = block(has_header | 1 << 29);
+        tag.extend_from_slice(items);
+        tag.extend(block(has_header));
+        tag
+    }
+
+    fn mp3(front: &[u8], audio: &[u8], back: &[u8]) -> Vec<u8> {
+        [front, audio, back].concat()
+    }
+
+    #[test]
+    fn mp3_tags_dont_change_the_hash() {
+        let audio = [0xff, 0xfb, 0x90, 0x64, 1, 2, 3, 4, 5, 6, 7, 8];
+        let bare = hash_of("bare.mp3", &audio);
+        let tagged = [
+            mp3(&id3v2(b"TIT2 Creep"), &audio, &[]),
+            mp3(&id3v2(b"TIT2 Karma Police, a longer title"), &audio, &[]),
+            mp3(&[], &audio, &id3v1("Creep")),
+            mp3(&[], &audio, &ape(b"Title\0Creep")),
+            mp3(
+                &id3v2(b"TIT2 Creep"),
+                &audio,
+                &[ape(b"x"), id3v1("Creep")].concat(),
+            ),
+        ];
+        for (i, file) in tagged.iter().enumerate() {
+            assert_eq!(hash_of(&format!("tagged{}.mp3", i), file), bare, "{}", i);
+        }
+
+        let mut changed = audio;
+        changed[6] ^= 1;
+        assert_ne!(hash_of("changed.mp3", &changed), bare);
+    }
+
+    fn flac(comment: &[u8], frames: &[u8]) -> Vec<u8> {
+        let mut file = b"fLaC".to_vec();
+        // STREAMINFO, then the comments as the last block
+        file.extend_from_slice(&[0x00, 0, 0, 34]);
+        file.extend_from_slice(&[7; 34]);
+        let size = comment.len() as u32;
+        file.push(0x84);
+        file.extend_from_slice(&size.to_be_bytes()[1..]);
+        file.extend_from_slice(comment);
+        file.extend_from_slice(frames);
+        file
+    }
+
+    #[test]
+    fn flac_comments_dont_change_the_hash() {
+        let frames = [0xff, 0xf8, 1, 2, 3, 4, 5, 6];
+        let hash = hash_of("a.flac", &flac(b"TITLE=Creep", &frames));
+        assert_eq!(
+            hash_of(
+                "b.flac",
+                &flac(b"TITLE=Karma Police;ARTIST=Radiohead", &frames)
+            ),
+            hash
+        );
+        assert_eq!(
+            hash_of("c.flac", &[id3v2(b"TIT2 x"), flac(b"", &frames)].concat()),
+            hash
+        );
+        assert_ne!(hash_of("d.flac", &flac(b"TITLE=Creep", &frames[..7])), hash);
+    }
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend(body);
+        file
+    }
+
+    #[test]
+    fn wav_metadata_chunks_dont_change_the_hash() {
+        let fmt = chunk(b"fmt ", &[1; 16]);
+        let data = chunk(b"data", &[1, 2, 3, 4, 5]);
+        let hash = hash_of("a.wav", &wav(&[fmt.clone(), data.clone()]));
+        let tagged = wav(&[
+            fmt.clone(),
+            chunk(b"LIST", b"INFOINAM\x05\0\0\0Creep"),
+            data.clone(),
+            chunk(b"id3 ", &id3v2(b"TIT2 Creep")),
+        ]);
+        assert_eq!(hash_of("b.wav", &tagged), hash);
+
+        let changed = chunk(b"data", &[1, 2, 3, 4, 6]);
+        assert_ne!(hash_of("c.wav", &wav(&[fmt, changed])), hash);
+    }
+
+    fn ogg_page(granule: u64, body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.push(1);
+        page.push(body.len() as u8);
+        page.extend_from_slice(body);
+        page
+    }
+
+    #[test]
+    fn ogg_comments_dont_change_the_hash() {
+        let ogg = |comments: &[u8], audio: &[u8]| {
+            [
+                ogg_page(0, b"\x01vorbis"),
+                ogg_page(0, comments),
+                ogg_page(4096, audio),
+            ]
+            .concat()
+        };
+        let hash = hash_of("a.ogg", &ogg(b"\x03vorbisTITLE=Creep", b"audio"));
+        assert_eq!(
+            hash_of("b.ogg", &ogg(b"\x03vorbisTITLE=Karma Police", b"audio")),
+            hash
+        );
+        assert_ne!(
+            hash_of("c.ogg", &ogg(b"\x03vorbisTITLE=Creep", b"audio!")),
+            hash
+        );
+    }
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = (body.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(body);
+        atom
+    }
+
+    #[test]
+    fn mp4_metadata_doesnt_change_the_hash() {
+        let mp4 = |moov: &[u8], mdat: &[u8]| {
+            [
+                atom(b"ftyp", b"M4A \0\0\0\0"),
+                atom(b"moov", moov),
+                atom(b"mdat", mdat),
+            ]
+            .concat()
+        };
+        let hash = hash_of("a.m4a", &mp4(b"udta:Creep", b"audio"));
+        assert_eq!(hash_of("b.m4a", &mp4(b"udta:Karma Police", b"audio")), hash);
+        assert_ne!(hash_of("c.m4a", &mp4(b"udta:Creep", b"audio!")), hash);
+    }
+
+    #[test]
+    fn files_without_audio_are_hashed_whole() {
+        // A WAV file with no data chunk
+        let fmt = chunk(b"fmt ", &[1; 16]);
+        let a = wav(&[fmt.clone(), chunk(b"LIST", b"one")]);
+        let b = wav(&[fmt, chunk(b"LIST", b"two")]);
+        assert_ne!(hash_of("a-empty.wav", &a), hash_of("b-empty.wav", &b));
+    }
+}
@@ -0,0 +1,233 @@
+//! Reading genres out of tags, where a track can have several and the same
+//! genre is spelled many different ways.
+
+use std::collections::HashMap;
+
+use lofty::id3::v1::GENRES;
+
+/// Extra aliases, mapping how a genre is written in tags to what it should
+/// be called. These are on top of, and take precedence over, the built-in
+/// ones.
+pub const GENRE_ALIASES_SETTING: &str = "genre_aliases";
+
+/// Common spellings of genres, in lowercase, and what they're called in the
+/// library
+const BUILT_IN_ALIASES: [(&str, &str); 16] = [
+    ("hip hop", "Hip-Hop"),
+    ("hiphop", "Hip-Hop"),
+    ("rap & hip-hop", "Hip-Hop"),
+    ("rnb", "R&B"),
+    ("r and b", "R&B"),
+    ("r'n'b", "R&B"),
+    ("rhythm and blues", "R&B"),
+    ("rock n roll", "Rock & Roll"),
+    ("rock'n'roll", "Rock & Roll"),
+    ("rock and roll", "Rock & Roll"),
+    ("drum and bass", "Drum & Bass"),
+    ("drum n bass", "Drum & Bass"),
+    ("dnb", "Drum & Bass"),
+    ("d&b", "Drum & Bass"),
+    ("synth pop", "Synthpop"),
+    ("synth-pop", "Synthpop"),
+];
+
+/// Splits and tidies up the genres in tags.
+pub struct GenreParser {
+    aliases: HashMap<String, String>,
+}
+
+impl GenreParser {
+    /// Makes a parser using the built-in aliases along with `custom` ones,
+    /// which map how genres are written to what they should be called.
+    pub fn new(custom: HashMap<String, String>) -> Self {
+        let mut aliases: HashMap<String, String> = BUILT_IN_ALIASES
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+        for (from, to) in custom {
+            aliases.insert(fold(&from), to);
+        }
+        Self { aliases }
+    }
+
+    /// Returns the genres in the values of every genre field or frame in a
+    /// tag, in order and without duplicates. Values can hold several genres
+    /// separated by `;`, `/` or null characters, or as ID3v1 numbers.
+    pub fn parse<'a>(&self, values: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let mut genres: Vec<String> = vec![];
+        for value in values {
+            for part in value.split([';', '/', '\0']) {
+                for genre in expand_id3v1(part) {
+                    if let Some(genre) = self.normalize(&genre) {
+                        if !genres.iter().any(|g| g.eq_ignore_ascii_case(&genre)) {
+                            genres.push(genre);
+                        }
+                    }
+                }
+            }
+        }
+        genres
+    }
+
+    /// Tidies up the spacing and case of a genre, and renames it if it has an
+    /// alias. Returns `None` for blank genres.
+    pub fn normalize(&self, genre: &str) -> Option<String> {
+        let genre = genre.split_whitespace().collect::<Vec<_>>().join(" ");
+        if genre.is_empty() {
+            return None;
+        }
+        if let Some(name) = self.aliases.get(&fold(&genre)) {
+            return Some(name.clone());
+        }
+        // Genres written all in lowercase are capitalised, but anything else
+        // is left alone as its capitals are probably deliberate, as in "IDM"
+        if genre.chars().any(char::is_uppercase) {
+            Some(genre)
+        } else {
+            Some(capitalize(&genre))
+        }
+    }
+}
+
+/// ID3v1 genres are numbers, which ID3v2.3 writes in brackets and can follow
+/// with a refinement, as in `(17)` or `(4)Eurodisco`. Also handles the
+/// special `(RX)` and `(CR)` codes for remixes and covers.
+fn expand_id3v1(value: &str) -> Vec<String> {
+    let value = value.trim();
+    if let Ok(i) = value.parse::<usize>() {
+        return GENRES.get(i).map(|g| g.to_string()).into_iter().collect();
+    }
+
+    let mut genres = vec![];
+    let mut rest = value;
+    while let Some(inner) = rest.strip_prefix('(') {
+        // `((` escapes a bracket at the start of a plain genre
+        if inner.starts_with('(') {
+            rest = inner;
+            break;
+        }
+        let end = match inner.find(')') {
+            Some(end) => end,
+            None => break,
+        };
+        let code = &inner[..end];
+        let genre = match code {
+            "RX" => Some("Remix"),
+            "CR" => Some("Cover"),
+            _ => code
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| GENRES.get(i).copied()),
+        };
+        match genre {
+            Some(g) => genres.push(g.to_string()),
+            // Not a code, so the brackets are part of the name
+            None => break,
+        }
+        rest = &inner[end + 1..];
+    }
+    if !rest.trim().is_empty() {
+        genres.push(rest.to_string());
+    }
+    genres
+}
+
+fn fold(genre: &str) -> String {
+    genre
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn capitalize(genre: &str) -> String {
+    let mut capitalized = String::with_capacity(genre.len());
+    let mut start_of_word = true;
+    for c in genre.chars() {
+        if start_of_word {
+            capitalized.extend(c.to_uppercase());
+        } else {
+            capitalized.push(c);
+        }
+        start_of_word = c.is_whitespace() || c == '-';
+    }
+    capitalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(values: &[&str]) -> Vec<String> {
+        GenreParser::new(HashMap::new()).parse(values.iter().copied())
+    }
+
+    #[test]
+    fn splits_values_holding_several_genres() {
+        assert_eq!(parse(&["Rock; Pop"]), ["Rock", "Pop"]);
+        assert_eq!(parse(&["Rock/Pop"]), ["Rock", "Pop"]);
+        assert_eq!(parse(&["Rock\0Pop\0"]), ["Rock", "Pop"]);
+        assert_eq!(parse(&["Rock", "Pop"]), ["Rock", "Pop"]);
+    }
+
+    #[test]
+    fn leaves_out_blank_and_repeated_genres() {
+        assert_eq!(parse(&["Rock;; ;rock", "ROCK"]), ["Rock"]);
+        assert!(parse(&["", "  "]).is_empty());
+    }
+
+    #[test]
+    fn reads_id3v1_numbers() {
+        assert_eq!(parse(&["17"]), ["Rock"]);
+        assert_eq!(parse(&["(17)"]), ["Rock"]);
+        assert_eq!(parse(&["(17)(4)"]), ["Rock", "Disco"]);
+        assert_eq!(parse(&["(4)Eurodisco"]), ["Disco", "Eurodisco"]);
+        assert_eq!(parse(&["(RX)(CR)"]), ["Remix", "Cover"]);
+        // Not numbers ID3v1 has a genre for
+        assert!(parse(&["9999"]).is_empty());
+    }
+
+    #[test]
+    fn keeps_brackets_that_are_part_of_a_name() {
+        assert_eq!(parse(&["((Bracketed)"]), ["(Bracketed)"]);
+        assert_eq!(parse(&["(Not a code) Rock"]), ["(Not a code) Rock"]);
+        assert_eq!(parse(&["(17"]), ["(17"]);
+    }
+
+    #[test]
+    fn tidies_up_spacing_and_case() {
+        let parser = GenreParser::new(HashMap::new());
+        assert_eq!(
+            parser.normalize("  indie   rock "),
+            Some("Indie Rock".into())
+        );
+        assert_eq!(parser.normalize("lo-fi"), Some("Lo-Fi".into()));
+        assert_eq!(parser.normalize("IDM"), Some("IDM".into()));
+        assert_eq!(parser.normalize("eLectro"), Some("eLectro".into()));
+        assert_eq!(parser.normalize(" "), None);
+    }
+
+    #[test]
+    fn renames_aliases() {
+        let parser = GenreParser::new(HashMap::new());
+        assert_eq!(parser.normalize("Hip  Hop"), Some("Hip-Hop".into()));
+        assert_eq!(parser.normalize("RnB"), Some("R&B".into()));
+        assert_eq!(
+            parser.normalize("drum and bass"),
+            Some("Drum & Bass".into())
+        );
+    }
+
+    #[test]
+    fn custom_aliases_take_precedence() {
+        let custom = HashMap::from([
+            ("Hip Hop".to_string(), "Rap".to_string()),
+            ("  Shoegazing ".to_string(), "Shoegaze".to_string()),
+        ]);
+        let parser = GenreParser::new(custom);
+        assert_eq!(parser.normalize("hip hop"), Some("Rap".into()));
+        assert_eq!(parser.normalize("shoegazing"), Some("Shoegaze".into()));
+        assert_eq!(parser.normalize("hiphop"), Some("Hip-Hop".into()));
+        assert_eq!(parser.parse(["Shoegazing; Shoegaze"]), ["Shoegaze"]);
+    }
+}
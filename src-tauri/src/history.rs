@@ -0,0 +1,58 @@
+use crate::models::{NumberField, NumberOp, Rule, SmartRules, SmartSort, SortField, Track};
+use crate::store::Store;
+use crate::Result;
+
+/// Records that the player finished a track, having listened to `listened`
+/// seconds of it.
+#[tauri::command]
+pub async fn record_play(
+    store: tauri::State<'_, Store>,
+    track_id: String,
+    listened: u32,
+) -> Result<()> {
+    store.record_play(&track_id, listened, false).await
+}
+
+/// Records that the player moved on from a track after `listened` seconds.
+#[tauri::command]
+pub async fn record_skip(
+    store: tauri::State<'_, Store>,
+    track_id: String,
+    listened: u32,
+) -> Result<()> {
+    store.record_play(&track_id, listened, true).await
+}
+
+#[tauri::command]
+pub async fn get_most_played(store: tauri::State<'_, Store>, limit: u32) -> Result<Vec<Track>> {
+    let rules = SmartRules {
+        rule: Rule::Number {
+            field: NumberField::PlayCount,
+            op: NumberOp::GreaterThan,
+            value: 0,
+        },
+        limit: Some(limit),
+        sort: Some(SmartSort {
+            field: SortField::PlayCount,
+            descending: true,
+        }),
+    };
+    store.get_smart_tracks(&rules).await
+}
+
+#[tauri::command]
+pub async fn get_recently_played(store: tauri::State<'_, Store>, limit: u32) -> Result<Vec<Track>> {
+    let rules = SmartRules {
+        rule: Rule::Number {
+            field: NumberField::PlayCount,
+            op: NumberOp::GreaterThan,
+            value: 0,
+        },
+        limit: Some(limit),
+        sort: Some(SmartSort {
+            field: SortField::LastPlayed,
+            descending: true,
+        }),
+    };
+    store.get_smart_tracks(&rules).await
+}
@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+use std::{fs, process::Command};
+
+use dotenvy::dotenv;
+use models::Track;
+use serde::{Serialize, Serializer};
+
+mod artwork;
+mod collation;
+mod controls;
+mod credits;
+mod fingerprint;
+mod genres;
+mod history;
+pub mod library;
+pub mod models;
+mod playlist_file;
+mod playlists;
+mod query;
+mod smart_playlist;
+mod tags;
+pub mod store;
+mod tray;
+mod watcher;
+
+use store::Store;
+use tauri::api::path::local_data_dir;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Controls error")]
+    Controls(souvlaki::Error),
+    #[error(transparent)]
+    Tagging(#[from] lofty::LoftyError),
+    #[error(transparent)]
+    FileScan(#[from] walkdir::Error),
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+    #[error(transparent)]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error(transparent)]
+    Glob(#[from] globset::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error("Missing data directory")]
+    MissingDataDir,
+    #[error("A library scan is already running")]
+    ScanRunning,
+    #[error("{0} tracks seem to have gone at once, so they were kept in case the folder is just unavailable")]
+    TooManyMissing(usize),
+    #[error("No {0} with id {1}")]
+    NotFound(&'static str, String),
+    #[error("Unsupported playlist format: {0}")]
+    UnsupportedPlaylist(String),
+    #[error("Ratings can't be saved in {0} tags")]
+    UnsupportedRating(String),
+    #[error("Smart playlists can't be edited by hand")]
+    SmartPlaylist,
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{1} at character {0} of the query")]
+    Query(usize, String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+}
+
+impl Error {
+    /// A short name for the kind of error, for grouping errors shown to the
+    /// user.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Controls(_) => "controls",
+            Error::Tagging(_) => "tagging",
+            Error::FileScan(_) => "file_scan",
+            Error::Watch(_) => "watch",
+            Error::ThreadPool(_) => "thread_pool",
+            Error::Glob(_) => "glob",
+            Error::Json(_) => "json",
+            Error::Xml(_) => "xml",
+            Error::Image(_) => "image",
+            Error::MissingDataDir => "missing_data_dir",
+            Error::ScanRunning => "scan_running",
+            Error::TooManyMissing(_) => "too_many_missing",
+            Error::NotFound(..) => "not_found",
+            Error::UnsupportedPlaylist(_) => "unsupported_playlist",
+            Error::UnsupportedRating(_) => "unsupported_rating",
+            Error::SmartPlaylist => "smart_playlist",
+            Error::Invalid(_) => "invalid",
+            Error::Query(..) => "query",
+            Error::Io(_) => "io",
+            Error::Sql(_) => "sql",
+            Error::Tauri(_) => "tauri",
+        }
+    }
+}
+
+impl From<souvlaki::Error> for Error {
+    fn from(e: souvlaki::Error) -> Self {
+        Self::Controls(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+fn create_data_dir() -> Result<PathBuf> {
+    let local_dir = local_data_dir().ok_or(Error::MissingDataDir)?;
+    let data_path = local_dir.join("tome");
+    fs::create_dir_all(&data_path)?;
+    Ok(data_path)
+}
+
+fn create_cache_dir() -> Result<PathBuf> {
+    let data_path = create_data_dir()?;
+    let cache_path = data_path.join("cache");
+    fs::create_dir_all(&cache_path)?;
+    Ok(cache_path)
+}
+
+#[tauri::command]
+async fn run_demucs(track: Track, out_dir: &str) -> Result<()> {
+    let mut child = Command::new("demucs/demucs")
+        .args([
+            "-o",
+            out_dir,
+            &track.path.to_string_lossy(),
+        ])
+        .spawn()?;
+    child.wait()?;
+    Ok(())
+}
+
+pub async fn run() -> Result<()> {
+    env_logger::init();
+    dotenv().ok();
+    let store = Store::new().await?;
+
+    tauri::Builder::default()
+        .setup(|app| {
+            controls::init_controls(app)?;
+            watcher::init_watcher(app)?;
+            Ok(())
+        })
+        .register_uri_scheme_protocol(artwork::ARTWORK_PROTOCOL, artwork::handle_request)
+        .system_tray(tray::new_tray())
+        .on_system_tray_event(tray::on_event)
+        .on_window_event(|event| match event.event() {
+            tauri::WindowEvent::CloseRequested { api, .. } => {
+                event.window().hide().unwrap();
+                api.prevent_close();
+            }
+            _ => {}
+        })
+        .invoke_handler(tauri::generate_handler![
+            controls::set_playback,
+            controls::set_metadata,
+            history::record_play,
+            history::record_skip,
+            history::get_most_played,
+            history::get_recently_played,
+            tags::update_track_metadata,
+            tags::set_track_rating,
+            tags::set_track_loved,
+            artwork::set_track_artwork,
+            artwork::remove_track_artwork,
+            artwork::set_album_artwork,
+            library::update_library,
+            library::cancel_scan,
+            library::clean_up_library,
+            library::get_scan_errors,
+            library::get_duplicate_files,
+            library::get_library_roots,
+            library::add_library_root,
+            library::remove_library_root,
+            library::get_setting,
+            library::set_setting,
+            library::get_artists,
+            library::get_genres,
+            library::get_albums,
+            library::get_playlists,
+            playlists::create_playlist,
+            playlists::rename_playlist,
+            playlists::delete_playlist,
+            playlists::get_playlist_entries,
+            playlists::add_track_to_playlist,
+            playlists::remove_track_from_playlist,
+            playlists::move_playlist_track,
+            playlists::import_playlist,
+            playlists::export_playlist,
+            playlists::create_smart_playlist,
+            playlists::set_playlist_rules,
+            playlists::get_smart_playlist_tracks,
+            playlists::preview_smart_playlist,
+            library::get_tracks,
+            library::search,
+            library::query_tracks,
+            run_demucs,
+        ])
+        .manage(store)
+        .manage(library::ScanJob::default())
+        .manage(artwork::PendingThumbnails::default())
+        .run(tauri::generate_context!())?;
+    Ok(())
+}
@@ -1,46 +1,933 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use lofty::{Accessor, AudioFile, PictureType, TaggedFileExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use lofty::{Accessor, AudioFile, ItemKey, PictureType, TaggedFileExt};
+use rayon::prelude::*;
+use tauri::Manager;
+use tokio::sync::mpsc;
 use walkdir::{DirEntry, WalkDir};
 
-use crate::models::{Album, Artist, Metadata, Playlist, Track};
+use crate::artwork::{cache_picture, remove_orphaned_thumbnails, FolderArtwork};
+use crate::credits::read_credits;
+use crate::fingerprint::audio_hash;
+use crate::genres::{GenreParser, GENRE_ALIASES_SETTING};
+use crate::models::{
+    Album, Artist, CleanupSummary, DuplicateFile, FileInfo, Genre, LibraryChanges, LibraryRoot,
+    Metadata, Page, Playlist, ScanError, ScanProgress, ScanSummary, ScannedTrack, SearchResults,
+    SmartSort, Track, TrackFile, TrackStats, VARIOUS_ARTISTS,
+};
+use crate::query;
 use crate::store::Store;
-use crate::{create_cache_dir, Result};
+use crate::tags::read_rating;
+use crate::watcher::LibraryWatcher;
+use crate::{create_cache_dir, Error, Result};
 
 const AUDIO_FILE_EXTS: [&str; 10] = [
     "m4a", "aac", "ape", "aif", "aiff", "aifc", "flac", "mp3", "ogg", "wav",
 ];
 
+const AUDIO_EXTENSIONS_SETTING: &str = "audio_extensions";
+
+/// Number of threads used to read tags, defaulting to one per CPU
+pub const SCAN_WORKERS_SETTING: &str = "scan_workers";
+
+/// Number of scanned tracks saved in each transaction, defaulting to
+/// `BATCH_SIZE`. Only there so the scan benchmark can compare batch sizes.
+pub const SCAN_BATCH_SIZE_SETTING: &str = "scan_batch_size";
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Scanned tracks are saved in transactions of this many
+const BATCH_SIZE: usize = 256;
+
+/// Cached artwork this new is left alone when cleaning up, as it may belong
+/// to a track that's still being saved
+const ARTWORK_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// How many of each kind of result a search returns unless told otherwise
+const SEARCH_LIMIT: u32 = 25;
+
+/// A scan that finds more than half of a library root's tracks missing won't
+/// remove them if there are at least this many
+const MASS_REMOVAL_MIN: usize = 20;
+
+impl ScanError {
+    pub fn new(path: &Path, error: &Error) -> Self {
+        Self {
+            path: path.into(),
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+            occurred_at: unix_timestamp(),
+        }
+    }
+}
+
+/// The library scan running in the background, if any.
+#[derive(Default)]
+pub struct ScanJob {
+    running: AtomicBool,
+    cancelled: Arc<AtomicBool>,
+    syncing: tokio::sync::Mutex<()>,
+}
+
+/// Held while the library is synced with the files on disk, see
+/// `ScanJob::lock`.
+pub type SyncGuard<'a> = tokio::sync::MutexGuard<'a, ()>;
+
+impl ScanJob {
+    /// Waits for any scan, watched change or tag edit being synced to
+    /// finish, so two of them never save and remove the same tracks at once.
+    /// Others wait until the guard is dropped.
+    pub async fn lock(&self) -> SyncGuard<'_> {
+        self.syncing.lock().await
+    }
+}
+
+/// Keeps track of how far a scan has got, reporting progress as it goes and
+/// letting it be cancelled part way through.
+pub struct ScanMonitor {
+    pub progress: ScanProgress,
+    errors: Vec<ScanError>,
+    cancelled: Arc<AtomicBool>,
+    on_progress: Box<dyn FnMut(&ScanProgress) + Send>,
+    last_report: Option<Instant>,
+}
+
+impl ScanMonitor {
+    pub fn new(
+        cancelled: Arc<AtomicBool>,
+        on_progress: impl FnMut(&ScanProgress) + Send + 'static,
+    ) -> Self {
+        Self {
+            progress: ScanProgress::default(),
+            errors: vec![],
+            cancelled,
+            on_progress: Box::new(on_progress),
+            last_report: None,
+        }
+    }
+
+    /// A monitor for updates nobody is watching, which can't be cancelled.
+    pub fn silent() -> Self {
+        Self::new(Arc::new(AtomicBool::new(false)), |_| {})
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Records a file that couldn't be scanned.
+    pub fn fail(&mut self, path: &Path, error: &Error) {
+        log::warn!("failed to scan {}: {}", path.display(), error);
+        self.progress.errors += 1;
+        self.errors.push(ScanError::new(path, error));
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ScanError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Reports progress, at most every `PROGRESS_INTERVAL`.
+    pub fn report(&mut self) {
+        if let Some(last) = self.last_report {
+            if last.elapsed() < PROGRESS_INTERVAL {
+                return;
+            }
+        }
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        (self.on_progress)(&self.progress);
+        self.last_report = Some(Instant::now());
+    }
+}
+
+#[tauri::command]
+pub async fn update_library(window: tauri::Window, job: tauri::State<'_, ScanJob>) -> Result<()> {
+    if job.running.swap(true, Ordering::SeqCst) {
+        return Err(Error::ScanRunning);
+    }
+    job.cancelled.store(false, Ordering::SeqCst);
+
+    tauri::async_runtime::spawn(async move {
+        let app = window.app_handle();
+        let job = app.state::<ScanJob>();
+        let store = app.state::<Store>();
+
+        let emitter = window.clone();
+        let mut monitor = ScanMonitor::new(job.cancelled.clone(), move |progress| {
+            if let Err(e) = emitter.emit("scan_progress", progress) {
+                log::warn!("failed to report scan progress: {}", e);
+            }
+        });
+        let guard = job.lock().await;
+        let result = scan_library(&window, &store, &mut monitor).await;
+        drop(guard);
+        if let Err(e) = &result {
+            log::error!("library scan failed: {}", e);
+        }
+
+        monitor.flush();
+        let (cleanup, error) = match result {
+            Ok(cleanup) => (cleanup, None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let summary = ScanSummary {
+            progress: monitor.progress.clone(),
+            cancelled: monitor.is_cancelled(),
+            error,
+            cleanup,
+        };
+        if let Err(e) = window.emit("scan_finished", summary) {
+            log::warn!("failed to report end of scan: {}", e);
+        }
+        job.running.store(false, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+/// Stops the running library scan, keeping whatever it has done so far.
+/// Returns whether there was a scan to cancel.
 #[tauri::command]
-pub async fn update_library(store: tauri::State<'_, Store>, search_paths: Vec<&str>) -> Result<()> {
+pub async fn cancel_scan(job: tauri::State<'_, ScanJob>) -> Result<bool> {
+    let running = job.running.load(Ordering::SeqCst);
+    if running {
+        job.cancelled.store(true, Ordering::SeqCst);
+    }
+    Ok(running)
+}
+
+/// Scans every library root, then cleans up after the tracks that have gone,
+/// returning what was cleaned up unless the scan was cancelled.
+async fn scan_library(
+    window: &tauri::Window,
+    store: &Store,
+    monitor: &mut ScanMonitor,
+) -> Result<Option<CleanupSummary>> {
+    let filters = get_file_filters(store).await?;
     let mut files = vec![];
-    for path in search_paths {
-        files.append(&mut get_audio_files(path)?);
+    // Roots that were read in full. A root that's missing or couldn't all be
+    // read, such as an unmounted drive, would otherwise look like its tracks
+    // had all been deleted.
+    let mut roots = vec![];
+    for filter in &filters {
+        let mut complete = true;
+        filter.walk_audio_files(&filter.root, |entry| {
+            match entry {
+                Ok(path) => {
+                    files.push(path);
+                    monitor.progress.discovered += 1;
+                }
+                Err(e) => {
+                    complete = false;
+                    let path = e.path().unwrap_or(&filter.root).to_path_buf();
+                    monitor.fail(&path, &e.into());
+                }
+            }
+            monitor.report();
+            !monitor.is_cancelled()
+        });
+        if complete && filter.root.is_dir() {
+            roots.push(filter.root.clone());
+        } else {
+            log::warn!(
+                "couldn't read all of {}, so no tracks will be removed from it",
+                filter.root.display()
+            );
+        }
     }
 
     log::debug!("have {} audio files", files.len());
+    if monitor.is_cancelled() {
+        store
+            .record_scan_errors(&[], &monitor.take_errors())
+            .await?;
+        return Ok(None);
+    }
+
+    let changes = sync_files(store, &roots, files, monitor).await?;
+    if !changes.is_empty() {
+        window.emit("library_changed", changes)?;
+    }
+    if monitor.is_cancelled() {
+        return Ok(None);
+    }
+    Ok(Some(clean_library(store).await?))
+}
+
+#[tauri::command]
+pub async fn clean_up_library(
+    job: tauri::State<'_, ScanJob>,
+    store: tauri::State<'_, Store>,
+) -> Result<CleanupSummary> {
+    // Waits for any sync, which could be about to use what would be removed
+    let _guard = job.lock().await;
+    clean_library(&store).await
+}
 
+/// Removes rows that no track refers to any more, along with cached artwork
+/// that nothing uses.
+pub async fn clean_library(store: &Store) -> Result<CleanupSummary> {
+    let mut summary = store.clean().await?;
+    let in_use = store.get_artwork_paths().await?;
     let cache_dir = create_cache_dir()?;
-    let prev_hashes = store.get_track_ids().await?;
-    for file in &files {
-        let c = md5::compute(file.path().to_string_lossy().as_bytes());
-        let hash = format!("{:x}", c);
-        if !prev_hashes.contains(&hash) {
-            let track = extract_track(hash, &file, &cache_dir)?;
-            store.update_genre(&track).await?;
-            store.update_artist(&track).await?;
-            store.update_album(&track).await?;
-            store.add_track(track).await?;
+    summary.artwork_files = tauri::async_runtime::spawn_blocking(move || {
+        let removed = remove_unused_artwork(&cache_dir, &in_use)?;
+        Ok::<_, Error>(removed + remove_orphaned_thumbnails(&cache_dir)?)
+    })
+    .await??;
+    log::debug!("cleaned up library: {:?}", summary);
+    Ok(summary)
+}
+
+fn remove_unused_artwork(cache_dir: &Path, in_use: &HashSet<PathBuf>) -> Result<u64> {
+    let mut removed = 0;
+    for entry in fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || in_use.contains(&path) {
+            continue;
+        }
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.elapsed().ok())
+            .unwrap_or_default();
+        if age < ARTWORK_GRACE_PERIOD {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => log::warn!("failed to remove {}: {}", path.display(), e),
+        }
+    }
+    Ok(removed)
+}
+
+#[tauri::command]
+pub async fn get_scan_errors(store: tauri::State<'_, Store>) -> Result<Vec<ScanError>> {
+    store.get_scan_errors().await
+}
+
+/// Lists the files left out of the library as copies of tracks already in
+/// it.
+#[tauri::command]
+pub async fn get_duplicate_files(store: tauri::State<'_, Store>) -> Result<Vec<DuplicateFile>> {
+    let mut files: Vec<DuplicateFile> = store
+        .get_duplicate_files()
+        .await?
+        .into_iter()
+        .map(|(file, _)| file)
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+#[tauri::command]
+pub async fn get_library_roots(store: tauri::State<'_, Store>) -> Result<Vec<LibraryRoot>> {
+    store.get_library_roots().await
+}
+
+#[tauri::command]
+pub async fn add_library_root(
+    store: tauri::State<'_, Store>,
+    watcher: tauri::State<'_, LibraryWatcher>,
+    root: LibraryRoot,
+) -> Result<()> {
+    // Catch bad exclude globs before they're saved
+    FileFilter::new(&root, &[])?;
+    // Watched first, so a root is never saved that isn't being watched
+    let newly_watched = watcher.watch(&root.path)?;
+    if let Err(e) = store.add_library_root(&root).await {
+        if newly_watched {
+            if let Err(e) = watcher.unwatch(&root.path) {
+                log::warn!("failed to stop watching {}: {}", root.path.display(), e);
+            }
         }
+        return Err(e);
     }
+    Ok(())
+}
 
+#[tauri::command]
+pub async fn remove_library_root(
+    window: tauri::Window,
+    store: tauri::State<'_, Store>,
+    watcher: tauri::State<'_, LibraryWatcher>,
+    path: PathBuf,
+) -> Result<()> {
+    store.remove_library_root(&path).await?;
+    watcher.unwatch(&path)?;
+
+    // Tracks can stay if they're also under another root
+    let roots = store.get_library_roots().await?;
+    let removed: Vec<String> = store
+        .get_track_files()
+        .await?
+        .into_values()
+        .filter(|t| t.path.starts_with(&path))
+        .filter(|t| !roots.iter().any(|r| t.path.starts_with(&r.path)))
+        .map(|t| t.id)
+        .collect();
+    store.delete_tracks(&removed).await?;
+    let duplicates: Vec<PathBuf> = store
+        .get_duplicate_files()
+        .await?
+        .into_iter()
+        .map(|(file, _)| file.path)
+        .filter(|p| p.starts_with(&path))
+        .filter(|p| !roots.iter().any(|r| p.starts_with(&r.path)))
+        .collect();
+    store.update_duplicate_files(&[], &duplicates).await?;
+
+    if !removed.is_empty() {
+        let changes = LibraryChanges {
+            removed,
+            ..Default::default()
+        };
+        window.emit("library_changed", changes)?;
+    }
     Ok(())
 }
 
 #[tauri::command]
-pub async fn get_artists(store: tauri::State<'_, Store>) -> Result<Vec<Artist>> {
-    store.get_artists().await
+pub async fn get_setting(
+    store: tauri::State<'_, Store>,
+    key: String,
+) -> Result<Option<serde_json::Value>> {
+    store.get_setting(&key).await
+}
+
+#[tauri::command]
+pub async fn set_setting(
+    store: tauri::State<'_, Store>,
+    key: String,
+    value: serde_json::Value,
+) -> Result<()> {
+    store.set_setting(&key, &value).await?;
+    Ok(())
+}
+
+pub async fn get_file_filters(store: &Store) -> Result<Vec<FileFilter>> {
+    let default_extensions: Vec<String> = match store.get_setting(AUDIO_EXTENSIONS_SETTING).await? {
+        Some(exts) => exts,
+        None => AUDIO_FILE_EXTS.iter().map(|e| e.to_string()).collect(),
+    };
+    store
+        .get_library_roots()
+        .await?
+        .iter()
+        .map(|root| FileFilter::new(root, &default_extensions))
+        .collect()
+}
+
+/// Decides which files under a library root belong in the library.
+pub struct FileFilter {
+    pub root: PathBuf,
+    follow_symlinks: bool,
+    include_hidden: bool,
+    extensions: HashSet<String>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    pub fn new(root: &LibraryRoot, default_extensions: &[String]) -> Result<Self> {
+        let allowed = if root.allowed_extensions.is_empty() {
+            default_extensions
+        } else {
+            &root.allowed_extensions
+        };
+        let denied: HashSet<String> = root
+            .denied_extensions
+            .iter()
+            .map(|e| normalise_extension(e))
+            .collect();
+        let extensions = allowed
+            .iter()
+            .map(|e| normalise_extension(e))
+            .filter(|e| !denied.contains(e))
+            .collect();
+
+        let mut exclude = GlobSetBuilder::new();
+        for glob in &root.exclude_globs {
+            exclude.add(Glob::new(glob)?);
+        }
+
+        Ok(Self {
+            root: root.path.clone(),
+            follow_symlinks: root.follow_symlinks,
+            include_hidden: root.include_hidden,
+            extensions,
+            exclude: exclude.build()?,
+        })
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+
+    /// Whether `path` isn't hidden or excluded, ignoring its extension.
+    pub fn is_visible(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+        let hidden = relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        (self.include_hidden || !hidden) && !self.is_excluded(path)
+    }
+
+    pub fn includes(&self, path: &Path) -> bool {
+        self.is_visible(path) && self.has_audio_extension(path)
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.exclude.is_match(relative) || self.exclude.is_match(path)
+    }
+
+    fn has_audio_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|s| s.to_str())
+            .map(|ext| self.extensions.contains(&ext.to_lowercase()))
+            .unwrap_or(false)
+    }
+
+    /// Finds the audio files under `dir`, skipping anything that can't be read.
+    pub fn get_audio_files(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut files = vec![];
+        self.walk_audio_files(dir, |entry| {
+            match entry {
+                Ok(path) => files.push(path),
+                Err(e) => log::warn!("failed to read {}: {}", dir.display(), e),
+            }
+            true
+        });
+        files
+    }
+
+    /// Calls `on_entry` with each audio file under `dir`, or the error hit
+    /// trying to read it, until it returns false.
+    pub fn walk_audio_files(
+        &self,
+        dir: &Path,
+        mut on_entry: impl FnMut(walkdir::Result<PathBuf>) -> bool,
+    ) {
+        let walker = WalkDir::new(dir)
+            .follow_links(self.follow_symlinks)
+            .into_iter();
+        let visible = |e: &DirEntry| {
+            (e.depth() == 0 || self.include_hidden || !is_hidden(e)) && !self.is_excluded(e.path())
+        };
+        for entry in walker.filter_entry(visible) {
+            let keep_going = match entry {
+                Ok(file) if file.file_type().is_file() && self.has_audio_extension(file.path()) => {
+                    on_entry(Ok(file.into_path()))
+                }
+                Ok(_) => true,
+                Err(e) => on_entry(Err(e)),
+            };
+            if !keep_going {
+                break;
+            }
+        }
+    }
+}
+
+fn normalise_extension(ext: &str) -> String {
+    ext.trim_start_matches('.').to_lowercase()
+}
+
+/// Brings the library in line with the audio `files` found under `roots`.
+/// New files are added, changed files have their tags re-read, and tracks
+/// under `roots` whose files weren't found are either matched up with a new
+/// file they were moved to or removed.
+///
+/// Tags are read on a pool of worker threads and saved in batches. Files that
+/// can't be read are skipped and counted as errors. If the scan is cancelled,
+/// everything read so far is kept but nothing is removed, since not every
+/// file has been seen. Nothing is removed from a library root that seems to
+/// have lost most of its tracks at once either.
+pub async fn sync_files(
+    store: &Store,
+    roots: &[PathBuf],
+    files: Vec<PathBuf>,
+    monitor: &mut ScanMonitor,
+) -> Result<LibraryChanges> {
+    let mut changes = LibraryChanges::default();
+    let mut known = store.get_track_files().await?;
+    let root_sizes: Vec<(PathBuf, usize)> = store
+        .get_library_roots()
+        .await?
+        .into_iter()
+        .map(|root| {
+            let size = known.keys().filter(|p| p.starts_with(&root.path)).count();
+            (root.path, size)
+        })
+        .collect();
+
+    // Audio already in the library and the track it belongs to, so the same
+    // file in another place isn't added twice
+    let mut seen_hashes = HashMap::new();
+    for track in known.values() {
+        seen_hashes.insert(track.id.clone(), track.id.clone());
+        if let Some(hash) = &track.content_hash {
+            seen_hashes.insert(hash.clone(), track.id.clone());
+        }
+    }
+    // Files already found to be copies, which don't need reading again
+    // until they change
+    let mut duplicates: HashMap<PathBuf, FileInfo> = store
+        .get_duplicate_files()
+        .await?
+        .into_iter()
+        .map(|(file, info)| (file.path, info))
+        .collect();
+
+    // Only files that are new or changed since the last scan need reading,
+    // along with any that were added before content hashes were stored
+    let mut to_read = vec![];
+    for path in files {
+        if monitor.is_cancelled() {
+            break;
+        }
+        let prev = known.remove(&path);
+        let duplicate = duplicates.remove(&path);
+        match file_info(&path) {
+            Ok(info)
+                if prev
+                    .as_ref()
+                    .map_or(false, |p| p.info == info && p.content_hash.is_some())
+                    || duplicate == Some(info) =>
+            {
+                monitor.progress.processed += 1;
+            }
+            Ok(info) => to_read.push((path, info, prev)),
+            Err(e) => {
+                monitor.fail(&path, &e);
+                monitor.progress.processed += 1;
+            }
+        }
+        monitor.report();
+    }
+
+    log::debug!("reading tags from {} files", to_read.len());
+    let workers = store
+        .get_setting::<usize>(SCAN_WORKERS_SETTING)
+        .await?
+        .unwrap_or(0);
+    let aliases = store
+        .get_setting(GENRE_ALIASES_SETTING)
+        .await?
+        .unwrap_or_default();
+    let genres = GenreParser::new(aliases);
+    let batch_size = store
+        .get_setting::<usize>(SCAN_BATCH_SIZE_SETTING)
+        .await?
+        .unwrap_or(BATCH_SIZE)
+        .max(1);
+    let mut results = read_tracks(to_read, workers, genres, monitor.cancelled.clone())?;
+    let mut batch = vec![];
+    let mut new_tracks = vec![];
+    while let Some((path, result)) = results.recv().await {
+        match result {
+            Ok(scanned) if scanned.prev.is_some() => {
+                seen_hashes.insert(scanned.content_hash.clone(), scanned.track.id.clone());
+                batch.push(scanned);
+            }
+            Ok(scanned) => new_tracks.push(scanned),
+            Err(e) => monitor.fail(&path, &e),
+        }
+        monitor.progress.current_path = Some(path);
+        monitor.progress.processed += 1;
+        monitor.report();
+
+        if batch.len() >= batch_size {
+            let saved = save_batch(store, std::mem::take(&mut batch), monitor).await;
+            changes.updated.extend(saved);
+        }
+    }
+    let saved = save_batch(store, batch, monitor).await;
+    changes.updated.extend(saved);
+
+    // Anything left over wasn't found on disk, but only tracks under the
+    // scanned roots can be considered gone
+    let mut missing: Vec<TrackFile> = known
+        .into_values()
+        .filter(|t| roots.iter().any(|r| t.path.starts_with(r)))
+        .collect();
+
+    let mut moved = vec![];
+    let mut added = vec![];
+    let mut found_duplicates = vec![];
+    for mut scanned in new_tracks {
+        match find_moved(store, &scanned, &missing).await {
+            Ok(Some(_)) if monitor.is_cancelled() => {
+                // The match may just be a file we haven't got to yet, so
+                // leave it for the next full scan to sort out
+            }
+            Ok(Some(i)) => {
+                let prev = missing.swap_remove(i);
+                log::debug!(
+                    "{} moved to {}",
+                    prev.path.display(),
+                    scanned.track.path.display()
+                );
+                scanned.track.id = prev.id.clone();
+                scanned.prev = Some(prev);
+                moved.push(scanned);
+            }
+            Ok(None) => match seen_hashes.get(&scanned.content_hash) {
+                Some(track_id) => {
+                    log::debug!("{} is already in the library", scanned.track.path.display());
+                    let file = DuplicateFile {
+                        path: scanned.track.path,
+                        track_id: track_id.clone(),
+                    };
+                    found_duplicates.push((file, scanned.info));
+                }
+                None => {
+                    seen_hashes.insert(scanned.content_hash.clone(), scanned.track.id.clone());
+                    added.push(scanned);
+                }
+            },
+            Err(e) => monitor.fail(&scanned.track.path, &e),
+        }
+    }
+    for chunk in chunks(moved, batch_size) {
+        changes
+            .updated
+            .extend(save_batch(store, chunk, monitor).await);
+    }
+    for chunk in chunks(added, batch_size) {
+        changes
+            .added
+            .extend(save_batch(store, chunk, monitor).await);
+    }
+
+    // Copies that weren't found again under the scanned roots have gone, or
+    // were read and turned out not to be copies after all
+    let gone_duplicates: Vec<PathBuf> = if monitor.is_cancelled() {
+        vec![]
+    } else {
+        duplicates
+            .into_keys()
+            .filter(|path| roots.iter().any(|r| path.starts_with(r)))
+            .collect()
+    };
+    store
+        .update_duplicate_files(&found_duplicates, &gone_duplicates)
+        .await?;
+
+    // Old errors can only be cleared once every file has been looked at again
+    if monitor.is_cancelled() {
+        store
+            .record_scan_errors(&[], &monitor.take_errors())
+            .await?;
+        return Ok(changes);
+    }
+    let missing = hold_back_mass_removals(missing, &root_sizes, monitor);
+    store
+        .record_scan_errors(roots, &monitor.take_errors())
+        .await?;
+
+    changes.removed = missing.into_iter().map(|t| t.id).collect();
+    log::debug!("removing {} missing tracks", changes.removed.len());
+    store.delete_tracks(&changes.removed).await?;
+
+    Ok(changes)
+}
+
+/// Leaves out the missing tracks of any library root that would lose most of
+/// its tracks at once, recording an error for the root instead. That's more
+/// likely to be a drive that isn't mounted than music that was deleted.
+fn hold_back_mass_removals(
+    mut missing: Vec<TrackFile>,
+    root_sizes: &[(PathBuf, usize)],
+    monitor: &mut ScanMonitor,
+) -> Vec<TrackFile> {
+    for (root, size) in root_sizes {
+        let gone = missing.iter().filter(|t| t.path.starts_with(root)).count();
+        if gone >= MASS_REMOVAL_MIN && gone * 2 > *size {
+            monitor.fail(root, &Error::TooManyMissing(gone));
+            missing.retain(|t| !t.path.starts_with(root));
+        }
+    }
+    missing
+}
+
+/// Reads tags from `files` on a pool of `workers` threads, or one per CPU if
+/// `workers` is 0, sending back each result as soon as it's ready.
+fn read_tracks(
+    files: Vec<(PathBuf, FileInfo, Option<TrackFile>)>,
+    workers: usize,
+    genres: GenreParser,
+    cancelled: Arc<AtomicBool>,
+) -> Result<mpsc::Receiver<(PathBuf, Result<ScannedTrack>)>> {
+    let cache_dir = create_cache_dir()?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()?;
+    let (tx, rx) = mpsc::channel(BATCH_SIZE);
+    let folders = FolderArtwork::default();
+    tokio::task::spawn_blocking(move || {
+        pool.install(|| {
+            files
+                .into_par_iter()
+                .for_each_with(tx, |tx, (path, info, prev)| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let result = read_track(&path, info, prev, &cache_dir, &folders, &genres);
+                    // Nobody is listening if the scan failed, so there's
+                    // nothing to do with the result
+                    let _ = tx.blocking_send((path, result));
+                })
+        })
+    });
+    Ok(rx)
+}
+
+/// Reads a file's tags and hashes its audio. New tracks are identified by
+/// their audio, while tracks already in the library keep their ids.
+fn read_track(
+    path: &Path,
+    info: FileInfo,
+    prev: Option<TrackFile>,
+    cache_dir: &Path,
+    folders: &FolderArtwork,
+    genres: &GenreParser,
+) -> Result<ScannedTrack> {
+    let content_hash = audio_hash(path)?;
+    let id = match &prev {
+        Some(p) => p.id.clone(),
+        None => content_hash.clone(),
+    };
+    let track = extract_track(id, path, cache_dir, genres)?;
+    let folder_artwork = path.parent().and_then(|dir| folders.get(cache_dir, dir));
+    Ok(ScannedTrack {
+        track,
+        info,
+        prev,
+        content_hash,
+        folder_artwork,
+    })
+}
+
+/// Saves `batch` in one transaction, falling back to saving tracks one at a
+/// time if that fails so a single bad track doesn't lose the whole batch.
+/// Returns the ids of the tracks that were saved.
+async fn save_batch(
+    store: &Store,
+    batch: Vec<ScannedTrack>,
+    monitor: &mut ScanMonitor,
+) -> Vec<String> {
+    if batch.is_empty() {
+        return vec![];
+    }
+    if let Err(e) = store.save_tracks(&batch).await {
+        log::warn!(
+            "failed to save batch of tracks, retrying individually: {}",
+            e
+        );
+    } else {
+        return batch.into_iter().map(|s| s.track.id).collect();
+    }
+
+    let mut saved = vec![];
+    for scanned in batch {
+        let scanned = [scanned];
+        match store.save_tracks(&scanned).await {
+            Ok(()) => saved.push(scanned[0].track.id.clone()),
+            Err(e) => monitor.fail(&scanned[0].track.path, &e),
+        }
+    }
+    saved
+}
+
+fn chunks(tracks: Vec<ScannedTrack>, size: usize) -> Vec<Vec<ScannedTrack>> {
+    let mut chunks = vec![];
+    let mut tracks = tracks.into_iter().peekable();
+    while tracks.peek().is_some() {
+        chunks.push(tracks.by_ref().take(size).collect());
+    }
+    chunks
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Looks for a track that disappeared from disk during this scan and has the
+/// same audio as `scanned`, returning its index in `missing`.
+async fn find_moved(
+    store: &Store,
+    scanned: &ScannedTrack,
+    missing: &[TrackFile],
+) -> Result<Option<usize>> {
+    let same_audio = missing
+        .iter()
+        .position(|t| t.content_hash.as_ref() == Some(&scanned.content_hash));
+    if same_audio.is_some() {
+        return Ok(same_audio);
+    }
+
+    // Tracks from before content hashes were stored can only be matched up
+    // by their tags
+    let track = &scanned.track;
+    for (i, candidate) in missing.iter().enumerate() {
+        if candidate.content_hash.is_some() || candidate.info.size != scanned.info.size {
+            continue;
+        }
+        if let Some(prev) = store.get_track(&candidate.id).await? {
+            if prev.duration == track.duration
+                && prev.metadata.title == track.metadata.title
+                && prev.metadata.artist == track.metadata.artist
+                && prev.metadata.album == track.metadata.album
+            {
+                return Ok(Some(i));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn file_info(path: &Path) -> Result<FileInfo> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    Ok(FileInfo {
+        size: metadata.len() as i64,
+        modified,
+    })
+}
+
+/// Returns a page of the artists in the library. Without a limit, returns
+/// every artist from `offset` on.
+#[tauri::command]
+pub async fn get_artists(
+    store: tauri::State<'_, Store>,
+    descending: Option<bool>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Page<Artist>> {
+    store
+        .get_artists(descending.unwrap_or(false), offset.unwrap_or(0), limit)
+        .await
 }
 
 #[tauri::command]
@@ -48,28 +935,84 @@ pub async fn get_playlists(store: tauri::State<'_, Store>) -> Result<Vec<Playlis
     store.get_playlists().await
 }
 
+/// Returns a page of the albums in the library, or those with one artist on
+/// any of their tracks or with tracks in one genre. Without a limit, returns
+/// every album from `offset` on.
 #[tauri::command]
 pub async fn get_albums(
     store: tauri::State<'_, Store>,
     artist: Option<String>,
-) -> Result<Vec<Album>> {
-    if let Some(a) = artist {
-        store.get_albums_from_artist(a).await
-    } else {
-        store.get_albums().await
-    }
+    genre: Option<String>,
+    sort: Option<SmartSort>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Page<Album>> {
+    store
+        .get_albums(
+            artist.as_deref(),
+            genre.as_deref(),
+            sort.as_ref(),
+            offset.unwrap_or(0),
+            limit,
+        )
+        .await
 }
 
+/// Returns a page of the tracks in the library, or on one album or in one
+/// genre. Without a limit, returns every track from `offset` on.
 #[tauri::command]
 pub async fn get_tracks(
     store: tauri::State<'_, Store>,
     album: Option<String>,
-) -> Result<Vec<Track>> {
-    if let Some(a) = album {
-        store.get_tracks_from_album(a).await
-    } else {
-        store.get_tracks().await
-    }
+    genre: Option<String>,
+    sort: Option<SmartSort>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Page<Track>> {
+    store
+        .get_tracks(
+            album.as_deref(),
+            genre.as_deref(),
+            sort.as_ref(),
+            offset.unwrap_or(0),
+            limit,
+        )
+        .await
+}
+
+/// Returns every genre, with how many tracks and albums are in each.
+#[tauri::command]
+pub async fn get_genres(store: tauri::State<'_, Store>) -> Result<Vec<Genre>> {
+    store.get_genres().await
+}
+
+/// Searches the titles and artists of tracks, albums and artists, matching
+/// words by their beginnings and ignoring accents. Returns up to `limit` of
+/// each.
+#[tauri::command]
+pub async fn search(
+    store: tauri::State<'_, Store>,
+    query: String,
+    limit: Option<u32>,
+) -> Result<SearchResults> {
+    store.search(&query, limit.unwrap_or(SEARCH_LIMIT)).await
+}
+
+/// Returns a page of the tracks matching a query written in the syntax
+/// described in `query.rs`, such as `artist:radiohead year:>=2000 -live`.
+/// Without a limit, returns every match from `offset` on.
+#[tauri::command]
+pub async fn query_tracks(
+    store: tauri::State<'_, Store>,
+    query: String,
+    sort: Option<SmartSort>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<Page<Track>> {
+    let rule = query::parse(&query)?;
+    store
+        .query_tracks(&rule, sort.as_ref(), offset.unwrap_or(0), limit)
+        .await
 }
 
 fn is_hidden(entry: &DirEntry) -> bool {
@@ -89,99 +1032,80 @@ fn none_if_empty(x: impl Into<String>) -> Option<String> {
     }
 }
 
-fn process_genre(s: impl Into<String>) -> String {
-    let g: String = s.into();
-    let mut start_num = false;
-    let mut num = String::new();
-    for c in g.chars() {
-        match c {
-            '(' => start_num = true,
-            ')' => start_num = false,
-            x => {
-                if start_num {
-                    num.push(x)
-                }
-            }
-        }
-    }
-
-    use lofty::id3::v1::GENRES;
-    num.parse::<usize>()
-        .ok()
-        .and_then(|i| GENRES.get(i))
-        .map(|x| x.to_string())
-        .unwrap_or(g)
-}
-
-fn extract_track(hash: String, file: &DirEntry, cache_dir: &PathBuf) -> Result<Track> {
-    let tag_file = lofty::read_from_path(file.path())?;
+fn extract_track(
+    id: String,
+    path: &Path,
+    cache_dir: &Path,
+    genre_parser: &GenreParser,
+) -> Result<Track> {
+    let tag_file = lofty::read_from_path(path)?;
     if let Some(tag) = tag_file.primary_tag().or(tag_file.first_tag()) {
         let song_artist = tag.artist().and_then(none_if_empty);
         let album_artist = tag
-            .get_string(&lofty::ItemKey::AlbumArtist)
-            .and_then(none_if_empty)
-            .map(|x| x.into());
+            .get_string(&ItemKey::AlbumArtist)
+            .and_then(none_if_empty);
+        let compilation = tag
+            .get_string(&ItemKey::FlagCompilation)
+            .map_or(false, |c| c == "1");
+        let musicbrainz_album_id = tag
+            .get_string(&ItemKey::MusicBrainzReleaseId)
+            .and_then(none_if_empty);
+        let artist = album_artist
+            .clone()
+            .or_else(|| compilation.then(|| VARIOUS_ARTISTS.to_string()))
+            .or_else(|| song_artist.clone())
+            .unwrap_or_default();
 
-        let mut artwork_path = None;
-        if let Some(cover_art) = tag
+        let title = tag
+            .title()
+            .and_then(none_if_empty)
+            .unwrap_or(path.file_name().unwrap().to_string_lossy().into());
+        let credits = read_credits(tag, &title, album_artist.as_deref());
+        let genres = genre_parser.parse(tag.get_strings(&ItemKey::Genre));
+        let artwork_path = match tag
             .get_picture_type(PictureType::CoverFront)
             .or(tag.get_picture_type(PictureType::Other))
         {
-            let path = cache_dir.join(&hash);
-            fs::write(&path, cover_art.data())?;
-            artwork_path = Some(path);
-        }
+            Some(cover_art) => Some(cache_picture(cache_dir, cover_art)?),
+            None => None,
+        };
 
         let track = Track {
-            id: hash,
+            id,
             metadata: Metadata {
-                title: tag
-                    .title()
-                    .and_then(none_if_empty)
-                    .unwrap_or(file.path().file_name().unwrap().to_string_lossy().into()),
-                artist: album_artist
-                    .clone()
-                    .or(song_artist.clone())
-                    .unwrap_or_default(),
-                song_artist: album_artist
-                    .and_then(|a| song_artist.and_then(|s| if s == a { None } else { Some(s) })),
+                title,
+                song_artist: song_artist.filter(|s| *s != artist),
+                artist,
                 album: tag.album().and_then(none_if_empty).unwrap_or_default(),
-                genre: tag.genre().and_then(none_if_empty).map(process_genre),
+                genre: genres.first().cloned(),
+                genres,
+                credits,
                 cd_number: tag.disk(),
                 track_number: tag.track(),
                 year: tag.year(),
                 artwork_path,
+                album_artist,
+                compilation,
+                musicbrainz_album_id,
+                rating: read_rating(tag),
+                loved: false,
             },
             duration: tag_file.properties().duration().as_secs() as u32,
-            path: file.path().into(),
+            path: path.into(),
+            stats: TrackStats::default(),
         };
         Ok(track)
     } else {
         let track = Track {
-            id: hash,
+            id,
             metadata: Metadata {
-                title: file.path().file_name().unwrap().to_string_lossy().into(),
+                title: path.file_name().unwrap().to_string_lossy().into(),
                 ..Default::default()
             },
             duration: tag_file.properties().duration().as_secs() as u32,
-            path: file.path().into(),
+            path: path.into(),
+            stats: TrackStats::default(),
         };
         Ok(track)
     }
 }
-
-fn get_audio_files(path: &str) -> Result<Vec<DirEntry>> {
-    let walker = WalkDir::new(path).into_iter();
-    let mut files = vec![];
-    for entry in walker.filter_entry(|e| !is_hidden(e)) {
-        let file = entry?;
-        if file.file_type().is_file() {
-            if let Some(ext) = file.path().extension().and_then(|s| s.to_str()) {
-                if AUDIO_FILE_EXTS.contains(&&*ext.to_lowercase()) {
-                    files.push(file);
-                }
-            }
-        }
-    }
-    Ok(files)
-}
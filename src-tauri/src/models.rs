@@ -1,7 +1,10 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::path::PathBuf;
 use ts_rs::TS;
 
+/// The album artist for compilations without one of their own
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+
 #[derive(Serialize, Deserialize, TS, Debug)]
 #[ts(export, export_to = "../src/bindings/")]
 pub struct Track {
@@ -9,6 +12,19 @@ pub struct Track {
     pub path: PathBuf,
     pub metadata: Metadata,
     pub duration: u32,
+    #[serde(default)]
+    pub stats: TrackStats,
+}
+
+/// How much a track has been listened to.
+#[derive(Serialize, Deserialize, TS, Debug, Default)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct TrackStats {
+    pub play_count: u32,
+    pub skip_count: u32,
+    /// Seconds since the Unix epoch
+    #[ts(type = "number | null")]
+    pub last_played: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, TS, Debug)]
@@ -21,8 +37,23 @@ pub struct Metadata {
     pub track_number: Option<u32>,
     pub cd_number: Option<u32>,
     pub year: Option<u32>,
+    /// The first of `genres`
     pub genre: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    /// Everyone credited on the track, with the main artists first
+    #[serde(default)]
+    pub credits: Vec<ArtistCredit>,
     pub artwork_path: Option<PathBuf>,
+    /// As tagged, where `artist` falls back to the track artist
+    pub album_artist: Option<String>,
+    #[serde(default)]
+    pub compilation: bool,
+    pub musicbrainz_album_id: Option<String>,
+    /// From 1 to 5 stars, or `None` if unrated
+    pub rating: Option<u8>,
+    #[serde(default)]
+    pub loved: bool,
 }
 
 impl Default for Metadata {
@@ -36,11 +67,236 @@ impl Default for Metadata {
             cd_number: None,
             year: None,
             genre: None,
+            genres: vec![],
+            credits: vec![],
             artwork_path: None,
+            album_artist: None,
+            compilation: false,
+            musicbrainz_album_id: None,
+            rating: None,
+            loved: false,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct ArtistCredit {
+    pub name: String,
+    pub role: ArtistRole,
+}
+
+/// What an artist did on a track.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum ArtistRole {
+    Main,
+    Featured,
+    Composer,
+    Conductor,
+    Remixer,
+}
+
+impl ArtistRole {
+    /// How the role is stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtistRole::Main => "main",
+            ArtistRole::Featured => "featured",
+            ArtistRole::Composer => "composer",
+            ArtistRole::Conductor => "conductor",
+            ArtistRole::Remixer => "remixer",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "main" => Some(ArtistRole::Main),
+            "featured" => Some(ArtistRole::Featured),
+            "composer" => Some(ArtistRole::Composer),
+            "conductor" => Some(ArtistRole::Conductor),
+            "remixer" => Some(ArtistRole::Remixer),
+            _ => None,
+        }
+    }
+}
+
+/// Changes to make to the tags of one or more tracks. Fields left out are
+/// kept as they are, while optional fields set to `null` are removed.
+#[derive(Deserialize, TS, Debug, Default)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct MetadataEdit {
+    #[ts(optional)]
+    pub title: Option<String>,
+    /// The track's own artist
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "string | null")]
+    pub artist: Option<Option<String>>,
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "string | null")]
+    pub album_artist: Option<Option<String>>,
+    #[ts(optional)]
+    pub album: Option<String>,
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "number | null")]
+    pub track_number: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "number | null")]
+    pub cd_number: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "number | null")]
+    pub year: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "some")]
+    #[ts(optional, type = "string | null")]
+    pub genre: Option<Option<String>>,
+    #[ts(optional)]
+    pub compilation: Option<bool>,
+}
+
+/// Tells a field that's present apart from one that's missing, so `null`
+/// can be told apart from leaving the field out.
+fn some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// The result of editing the tags of a batch of tracks.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct MetadataEditResult {
+    pub changes: LibraryChanges,
+    /// Files whose tags couldn't be written
+    pub errors: Vec<ScanError>,
+}
+
+/// Size and modification time of a track's file, used to detect changes between scans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    pub size: i64,
+    pub modified: i64,
+}
+
+/// A track as last seen on disk by a library scan.
+#[derive(Debug)]
+pub struct TrackFile {
+    pub id: String,
+    pub path: PathBuf,
+    pub album_id: String,
+    pub info: FileInfo,
+    pub content_hash: Option<String>,
+}
+
+/// A track read from disk by a scan, ready to be saved.
+#[derive(Debug)]
+pub struct ScannedTrack {
+    pub track: Track,
+    pub info: FileInfo,
+    /// The track as it was before the scan, if it was already in the library
+    pub prev: Option<TrackFile>,
+    pub content_hash: String,
+    /// The cover image kept in the track's folder, copied into the artwork
+    /// cache
+    pub folder_artwork: Option<PathBuf>,
+}
+
+/// A folder scanned for music, along with options controlling which files
+/// under it are part of the library.
+#[derive(Serialize, Deserialize, TS, Debug, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct LibraryRoot {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub include_hidden: bool,
+    /// Overrides the default audio file extensions when not empty
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+}
+
+/// Ids of the tracks touched by a library update, emitted to the window as
+/// `library_changed`.
+#[derive(Serialize, TS, Debug, Default, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct LibraryChanges {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl LibraryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Emitted to the window as `scan_progress` while the library is scanned.
+#[derive(Serialize, TS, Debug, Default, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct ScanProgress {
+    pub discovered: u32,
+    pub processed: u32,
+    pub current_path: Option<PathBuf>,
+    pub errors: u32,
+}
+
+/// Emitted to the window as `scan_finished` once a scan stops.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct ScanSummary {
+    pub progress: ScanProgress,
+    pub cancelled: bool,
+    pub error: Option<String>,
+    /// What was tidied up afterwards, unless the scan was cancelled or failed
+    pub cleanup: Option<CleanupSummary>,
+}
+
+/// What was removed when cleaning up the library.
+#[derive(Serialize, TS, Debug, Default)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct CleanupSummary {
+    #[ts(type = "number")]
+    pub artists: u64,
+    #[ts(type = "number")]
+    pub albums: u64,
+    #[ts(type = "number")]
+    pub genres: u64,
+    #[ts(type = "number")]
+    pub playlist_entries: u64,
+    /// Cached artwork that no track or album uses
+    #[ts(type = "number")]
+    pub artwork_files: u64,
+}
+
+/// A file that couldn't be added to the library during a scan.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub kind: String,
+    pub message: String,
+    #[ts(type = "number")]
+    pub occurred_at: i64,
+}
+
+/// A file left out of the library because its audio is already there as
+/// another track.
+#[derive(Serialize, TS, Debug, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct DuplicateFile {
+    pub path: PathBuf,
+    /// The track it's a copy of
+    pub track_id: String,
+}
+
 #[derive(Serialize, TS, Debug)]
 #[ts(export, export_to = "../src/bindings/")]
 pub struct Artist {
@@ -53,18 +309,196 @@ pub struct Album {
     pub id: String,
     pub title: String,
     pub artist: String,
-    pub track_count: u32,
     pub artwork_path: Option<PathBuf>,
+    pub year: Option<u32>,
+    pub compilation: bool,
+    pub track_count: u32,
+    /// In seconds
+    pub total_duration: u32,
+    pub disc_count: u32,
+    pub first_year: Option<u32>,
+    pub last_year: Option<u32>,
 }
 
 #[derive(Serialize, TS, Debug)]
 #[ts(export, export_to = "../src/bindings/")]
 pub struct Genre {
     pub name: String,
+    pub track_count: u32,
+    pub album_count: u32,
+}
+
+/// Part of a longer list, along with how long the whole list is.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u32,
+}
+
+/// What a search matched, best matches first.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct SearchResults {
+    pub tracks: Vec<Track>,
+    pub albums: Vec<Album>,
+    pub artists: Vec<Artist>,
 }
 
 #[derive(Serialize, TS, Debug)]
 #[ts(export, export_to = "../src/bindings/")]
 pub struct Playlist {
+    #[ts(type = "number")]
+    pub id: i64,
     pub title: String,
+    /// Set for smart playlists, whose tracks are whichever match the rules
+    pub rules: Option<SmartRules>,
+}
+
+/// What a smart playlist holds.
+#[derive(Serialize, Deserialize, TS, Debug, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct SmartRules {
+    pub rule: Rule,
+    pub limit: Option<u32>,
+    pub sort: Option<SmartSort>,
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rule {
+    /// Matches when every rule does, or always when there are none
+    All {
+        rules: Vec<Rule>,
+    },
+    /// Matches when any rule does, or never when there are none
+    Any {
+        rules: Vec<Rule>,
+    },
+    Text {
+        field: TextField,
+        op: TextOp,
+        value: String,
+    },
+    Number {
+        field: NumberField,
+        op: NumberOp,
+        #[ts(type = "number")]
+        value: i64,
+    },
+    /// Inclusive at both ends
+    Between {
+        field: NumberField,
+        #[ts(type = "number")]
+        min: i64,
+        #[ts(type = "number")]
+        max: i64,
+    },
+    AddedWithin {
+        days: u32,
+    },
+    PlayedWithin {
+        days: u32,
+    },
+    Loved {
+        loved: bool,
+    },
+    /// Matches when the rule doesn't, including when it can't tell because
+    /// a field is missing
+    Not {
+        rule: Box<Rule>,
+    },
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    Title,
+    /// The album artist or anyone credited on the track
+    Artist,
+    Album,
+    Genre,
+}
+
+/// Text comparisons ignore case.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum TextOp {
+    Is,
+    IsNot,
+    Contains,
+    NotContains,
+    StartsWith,
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum NumberField {
+    Year,
+    /// In seconds
+    Duration,
+    TrackNumber,
+    PlayCount,
+    SkipCount,
+    Rating,
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum NumberOp {
+    Is,
+    IsNot,
+    GreaterThan,
+    AtLeast,
+    LessThan,
+    AtMost,
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct SmartSort {
+    pub field: SortField,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Title,
+    Artist,
+    Album,
+    Year,
+    Duration,
+    AddedAt,
+    PlayCount,
+    LastPlayed,
+    Rating,
+    Random,
+}
+
+/// The result of importing a playlist file.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct PlaylistImport {
+    pub playlist: Playlist,
+    pub matched: u32,
+    /// Entries that aren't in the library, as written in the file
+    pub unmatched: Vec<String>,
+}
+
+/// A track's place in a playlist. The same track can have several entries.
+#[derive(Serialize, TS, Debug)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct PlaylistEntry {
+    #[ts(type = "number")]
+    pub id: i64,
+    pub position: u32,
+    pub track: Track,
 }
@@ -0,0 +1,559 @@
+//! Reading and writing playlists in the file formats other players use.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::{Error, Result};
+
+/// An entry in a playlist file.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileEntry {
+    /// A path, absolute or relative to the playlist file, or a URL
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// In seconds
+    pub duration: Option<u32>,
+}
+
+pub fn read(path: &Path) -> Result<Vec<FileEntry>> {
+    let bytes = fs::read(path)?;
+    match extension(path).as_str() {
+        "m3u8" => Ok(read_m3u(&String::from_utf8_lossy(&bytes))),
+        "m3u" => Ok(read_m3u(&decode_m3u(bytes))),
+        "pls" => Ok(read_pls(&String::from_utf8_lossy(&bytes))),
+        "xspf" => read_xspf(&String::from_utf8_lossy(&bytes)),
+        ext => Err(Error::UnsupportedPlaylist(ext.into())),
+    }
+}
+
+pub fn write(path: &Path, entries: &[FileEntry]) -> Result<()> {
+    let contents = match extension(path).as_str() {
+        "m3u" | "m3u8" => write_m3u(entries),
+        "pls" => write_pls(entries),
+        "xspf" => write_xspf(entries),
+        ext => return Err(Error::UnsupportedPlaylist(ext.into())),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Plain `.m3u` files are traditionally Latin-1, though plenty of players
+/// write UTF-8 anyway.
+fn decode_m3u(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect())
+}
+
+fn read_m3u(contents: &str) -> Vec<FileEntry> {
+    let mut entries = vec![];
+    let mut entry = FileEntry::default();
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>,<title>, where the seconds are -1 when
+            // unknown
+            let (duration, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            entry.duration = duration
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|d| *d >= 0)
+                .map(|d| d as u32);
+            entry.title = none_if_empty(title);
+        } else if let Some(artist) = line.strip_prefix("#EXTART:") {
+            entry.artist = none_if_empty(artist);
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            entry.album = none_if_empty(album);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let mut entry = std::mem::take(&mut entry);
+            entry.location = line.into();
+            // Titles are usually written as `<artist> - <title>`, but only
+            // an #EXTART line says for sure where the artist ends, as either
+            // could contain " - " themselves
+            if let (Some(artist), Some(title)) = (&entry.artist, &entry.title) {
+                let title = title
+                    .strip_prefix(artist.as_str())
+                    .and_then(|t| t.strip_prefix(" - "))
+                    .map(String::from);
+                if title.is_some() {
+                    entry.title = title;
+                }
+            }
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+fn write_m3u(entries: &[FileEntry]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for entry in entries {
+        if entry.title.is_some() || entry.duration.is_some() {
+            let duration = entry.duration.map_or(-1, |d| d as i64);
+            out.push_str(&format!("#EXTINF:{},{}\n", duration, join_title(entry)));
+        }
+        if let Some(artist) = &entry.artist {
+            out.push_str(&format!("#EXTART:{}\n", artist));
+        }
+        if let Some(album) = &entry.album {
+            out.push_str(&format!("#EXTALB:{}\n", album));
+        }
+        out.push_str(&entry.location);
+        out.push('\n');
+    }
+    out
+}
+
+fn read_pls(contents: &str) -> Vec<FileEntry> {
+    // Entries are numbered from 1, and their keys can come in any order
+    let mut entries = BTreeMap::<u32, FileEntry>::new();
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let (key, value) = match line.trim().split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let key = key.trim().to_lowercase();
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let (field, index) = key.split_at(split);
+        let index = match index.parse() {
+            Ok(i) => i,
+            Err(_) => continue,
+        };
+        let entry = entries.entry(index).or_default();
+        let value = value.trim();
+        match field {
+            "file" => entry.location = value.into(),
+            // Often `<artist> - <title>`, but with nothing to say where the
+            // artist ends it's all kept as the title
+            "title" => entry.title = none_if_empty(value),
+            "length" => {
+                entry.duration = value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|d| *d >= 0)
+                    .map(|d| d as u32)
+            }
+            _ => {}
+        }
+    }
+    entries
+        .into_values()
+        .filter(|e| !e.location.is_empty())
+        .collect()
+}
+
+fn write_pls(entries: &[FileEntry]) -> String {
+    let mut out = String::from("[playlist]\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        out.push_str(&format!("File{}={}\n", n, entry.location));
+        if entry.title.is_some() {
+            out.push_str(&format!("Title{}={}\n", n, join_title(entry)));
+        }
+        let duration = entry.duration.map_or(-1, |d| d as i64);
+        out.push_str(&format!("Length{}={}\n", n, duration));
+    }
+    out.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    out
+}
+
+fn read_xspf(contents: &str) -> Result<Vec<FileEntry>> {
+    let mut reader = Reader::from_str(contents);
+    reader.trim_text(true);
+
+    let mut entries = vec![];
+    let mut elements: Vec<String> = vec![];
+    let mut entry: Option<FileEntry> = None;
+    loop {
+        let text = match reader.read_event()? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if name == "track" {
+                    entry = Some(FileEntry::default());
+                }
+                elements.push(name);
+                continue;
+            }
+            Event::End(_) => {
+                if elements.pop().as_deref() == Some("track") {
+                    entries.extend(entry.take().filter(|e| !e.location.is_empty()));
+                }
+                continue;
+            }
+            Event::Text(t) => t.unescape()?.into_owned(),
+            Event::CData(t) => String::from_utf8_lossy(&t.into_inner()).into_owned(),
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        // Only the fields directly inside a track, so a track's extension
+        // elements can't be mistaken for them
+        let entry = match &mut entry {
+            Some(e) if elements.len() >= 2 && elements[elements.len() - 2] == "track" => e,
+            _ => continue,
+        };
+        match elements.last().map(String::as_str) {
+            Some("location") if entry.location.is_empty() => {
+                // Locations are URIs, so relative ones are percent-encoded too
+                entry.location = if text.contains("://") {
+                    text
+                } else {
+                    percent_decode(&text)
+                };
+            }
+            Some("title") => entry.title = Some(text),
+            Some("creator") => entry.artist = Some(text),
+            Some("album") => entry.album = Some(text),
+            // In milliseconds
+            Some("duration") => {
+                entry.duration = text.parse::<u64>().ok().map(|d| (d / 1000) as u32)
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+fn write_xspf(entries: &[FileEntry]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for entry in entries {
+        out.push_str("    <track>\n");
+        let location = location_uri(&entry.location);
+        out.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape(&location)
+        ));
+        let fields = [
+            ("title", &entry.title),
+            ("creator", &entry.artist),
+            ("album", &entry.album),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                out.push_str(&format!("      <{0}>{1}</{0}>\n", name, escape(value)));
+            }
+        }
+        if let Some(duration) = entry.duration {
+            out.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                duration as u64 * 1000
+            ));
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+fn none_if_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.into())
+    }
+}
+
+/// Writes an entry's title the way players show it in M3U and PLS files.
+fn join_title(entry: &FileEntry) -> String {
+    match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (_, Some(title)) => title.clone(),
+        (_, None) => String::new(),
+    }
+}
+
+/// Turns a path into the URI XSPF expects, leaving URLs alone.
+fn location_uri(location: &str) -> String {
+    if location.contains("://") {
+        return location.into();
+    }
+    let path = Path::new(location);
+    let encoded = percent_encode(&location.replace('\\', "/"));
+    if !path.is_absolute() {
+        encoded
+    } else if encoded.starts_with('/') {
+        format!("file://{}", encoded)
+    } else {
+        // A Windows path like C:/Music
+        format!("file:///{}", encoded)
+    }
+}
+
+/// Works out the file an entry's location points to, resolving relative
+/// paths against `base_dir`. Returns `None` for remote URLs.
+pub fn resolve_location(location: &str, base_dir: &Path) -> Option<PathBuf> {
+    let path = if let Some(url) = location.strip_prefix("file://") {
+        // file:///home/... and file://localhost/home/... both name local files
+        let url = url.strip_prefix("localhost").unwrap_or(url);
+        PathBuf::from(percent_decode(url))
+    } else if location.contains("://") {
+        return None;
+    } else {
+        PathBuf::from(location)
+    };
+
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    };
+    if cfg!(not(windows)) && !path.exists() && location.contains('\\') {
+        // Written on Windows
+        return resolve_location(&location.replace('\\', "/"), base_dir);
+    }
+    Some(normalize(&path))
+}
+
+/// Returns `path` relative to `base_dir`, or `None` if they have no root in
+/// common, such as when they're on different drives.
+pub fn relative_path(path: &Path, base_dir: &Path) -> Option<PathBuf> {
+    let path = normalize(path);
+    let base_dir = normalize(base_dir);
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base_dir.components().peekable();
+    if path_parts.peek() != base_parts.peek() {
+        return None;
+    }
+    while path_parts.peek().is_some() && path_parts.peek() == base_parts.peek() {
+        path_parts.next();
+        base_parts.next();
+    }
+
+    let mut relative: PathBuf = base_parts.map(|_| Component::ParentDir).collect();
+    relative.extend(path_parts);
+    Some(relative)
+}
+
+/// Removes `.` and `..` components without touching the filesystem, so paths
+/// written different ways compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~:".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<FileEntry> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/playlists")
+            .join(name);
+        read(&path).unwrap()
+    }
+
+    fn entry(location: &str) -> FileEntry {
+        FileEntry {
+            location: location.into(),
+            ..Default::default()
+        }
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.into())
+    }
+
+    #[test]
+    fn reads_extended_m3u() {
+        let entries = fixture("extended.m3u8");
+        assert_eq!(
+            entries,
+            [
+                FileEntry {
+                    title: some("Jóga"),
+                    artist: some("Björk"),
+                    album: some("Homogenic"),
+                    duration: Some(296),
+                    ..entry("Björk/Homogenic/03 Jóga.flac")
+                },
+                // Without #EXTART there's no telling where the artist ends
+                FileEntry {
+                    title: some("Sigur Rós - Svefn-g-englar"),
+                    ..entry("../Sigur Rós/Ágætis byrjun/02 Svefn-g-englar.mp3")
+                },
+                FileEntry {
+                    title: some("Untitled"),
+                    duration: Some(180),
+                    ..entry("/music/untitled.mp3")
+                },
+                entry("http://radio.example.com/stream"),
+            ]
+        );
+        assert_eq!(read_m3u(&write_m3u(&entries)), entries);
+    }
+
+    #[test]
+    fn reads_latin1_m3u() {
+        let entries = fixture("latin1.m3u");
+        assert_eq!(
+            entries,
+            [FileEntry {
+                title: some("Mötley Crüe - Kickstart My Heart"),
+                duration: Some(283),
+                ..entry("Mötley Crüe\\Dr. Feelgood\\05 Kickstart My Heart.mp3")
+            }]
+        );
+        assert_eq!(read_m3u(&write_m3u(&entries)), entries);
+    }
+
+    #[test]
+    fn reads_pls() {
+        let entries = fixture("playlist.pls");
+        assert_eq!(
+            entries,
+            [
+                FileEntry {
+                    title: some("Björk - Jóga"),
+                    duration: Some(296),
+                    ..entry("Björk/Homogenic/03 Jóga.flac")
+                },
+                FileEntry {
+                    title: some("Svefn-g-englar"),
+                    ..entry("..\\Sigur Rós\\Ágætis byrjun\\02 Svefn-g-englar.mp3")
+                },
+                entry("http://radio.example.com/stream"),
+            ]
+        );
+        assert_eq!(read_pls(&write_pls(&entries)), entries);
+    }
+
+    #[test]
+    fn reads_xspf() {
+        let entries = fixture("playlist.xspf");
+        assert_eq!(
+            entries,
+            [
+                FileEntry {
+                    title: some("Jóga"),
+                    artist: some("Björk"),
+                    album: some("Homogenic"),
+                    duration: Some(296),
+                    ..entry("Björk/Homogenic/03 Jóga.flac")
+                },
+                FileEntry {
+                    title: some("Svefn-g-englar"),
+                    artist: some("Sigur Rós"),
+                    ..entry("file:///music/Sigur%20R%C3%B3s/%C3%81g%C3%A6tis%20byrjun/02%20Svefn-g-englar.mp3")
+                },
+                FileEntry {
+                    title: some("Rock & Roll"),
+                    ..entry("Rock & Roll.mp3")
+                },
+            ]
+        );
+        assert_eq!(read_xspf(&write_xspf(&entries)).unwrap(), entries);
+    }
+
+    #[test]
+    fn written_m3u_keeps_artists_with_separators() {
+        let entries = [FileEntry {
+            title: some("Ashes - Live"),
+            artist: some("Crosby, Stills - Nash"),
+            ..entry("live.mp3")
+        }];
+        assert_eq!(read_m3u(&write_m3u(&entries)), entries);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_locations() {
+        let base = Path::new("/playlists");
+        let m3u = fixture("extended.m3u8");
+        let pls = fixture("playlist.pls");
+        let xspf = fixture("playlist.xspf");
+        let resolve = |entry: &FileEntry| resolve_location(&entry.location, base);
+
+        assert_eq!(
+            resolve(&m3u[0]),
+            Some("/playlists/Björk/Homogenic/03 Jóga.flac".into())
+        );
+        let svefn = Some("/Sigur Rós/Ágætis byrjun/02 Svefn-g-englar.mp3".into());
+        assert_eq!(resolve(&m3u[1]), svefn);
+        // Written on Windows
+        assert_eq!(resolve(&pls[1]), svefn);
+        assert_eq!(
+            resolve(&xspf[1]),
+            Some("/music/Sigur Rós/Ágætis byrjun/02 Svefn-g-englar.mp3".into())
+        );
+        assert_eq!(resolve(&m3u[3]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn makes_relative_paths() {
+        assert_eq!(
+            relative_path(
+                Path::new("/music/Björk/Jóga.flac"),
+                Path::new("/music/lists")
+            ),
+            Some("../Björk/Jóga.flac".into())
+        );
+        assert_eq!(
+            relative_path(Path::new("/music/a.mp3"), Path::new("/music")),
+            Some("a.mp3".into())
+        );
+    }
+}
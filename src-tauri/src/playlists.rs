@@ -0,0 +1,235 @@
+use std::path::{Path, PathBuf};
+
+use crate::models::{Playlist, PlaylistEntry, PlaylistImport, SmartRules, Track};
+use crate::playlist_file::{self, relative_path, resolve_location, FileEntry};
+use crate::store::Store;
+use crate::{Error, Result};
+
+#[tauri::command]
+pub async fn create_playlist(store: tauri::State<'_, Store>, title: String) -> Result<Playlist> {
+    store.create_playlist(&title).await
+}
+
+#[tauri::command]
+pub async fn rename_playlist(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+    title: String,
+) -> Result<()> {
+    store.rename_playlist(playlist_id, &title).await
+}
+
+#[tauri::command]
+pub async fn delete_playlist(store: tauri::State<'_, Store>, playlist_id: i64) -> Result<()> {
+    store.delete_playlist(playlist_id).await
+}
+
+#[tauri::command]
+pub async fn get_playlist_entries(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+) -> Result<Vec<PlaylistEntry>> {
+    store.get_playlist_entries(playlist_id).await
+}
+
+/// Adds a track at `position`, or to the end of the playlist if no position
+/// is given, returning the id of the new entry.
+#[tauri::command]
+pub async fn add_track_to_playlist(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+    track_id: String,
+    position: Option<i64>,
+) -> Result<i64> {
+    store
+        .add_track_to_playlist(&track_id, playlist_id, position.unwrap_or(i64::MAX))
+        .await
+}
+
+#[tauri::command]
+pub async fn remove_track_from_playlist(
+    store: tauri::State<'_, Store>,
+    entry_id: i64,
+) -> Result<()> {
+    store.remove_track_from_playlist(entry_id).await?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn move_playlist_track(
+    store: tauri::State<'_, Store>,
+    entry_id: i64,
+    position: i64,
+) -> Result<()> {
+    store.move_playlist_track(entry_id, position).await?;
+    Ok(())
+}
+
+/// Imports an M3U, PLS or XSPF playlist file, matching its entries to tracks
+/// in the library by path, or by their tags if the paths don't match. The
+/// playlist is named after the file unless given a title.
+#[tauri::command]
+pub async fn import_playlist(
+    store: tauri::State<'_, Store>,
+    path: PathBuf,
+    title: Option<String>,
+) -> Result<PlaylistImport> {
+    // Resolving locations checks which files exist, so it's done off the
+    // async runtime along with reading the file
+    let file = path.clone();
+    let entries = tauri::async_runtime::spawn_blocking(move || {
+        let base_dir = file.parent().unwrap_or_else(|| Path::new(""));
+        let entries = playlist_file::read(&file)?;
+        let resolved = entries
+            .into_iter()
+            .map(|entry| {
+                let location = resolve_location(&entry.location, base_dir);
+                (entry, location)
+            })
+            .collect::<Vec<_>>();
+        Ok::<_, Error>(resolved)
+    })
+    .await??;
+    let library = store.get_track_files().await?;
+
+    let mut track_ids = vec![];
+    let mut unmatched = vec![];
+    for (entry, location) in entries {
+        let mut track_id = location
+            .and_then(|p| library.get(&p))
+            .map(|track| track.id.clone());
+        if track_id.is_none() {
+            // The file may have been made on another machine, or before the
+            // music was moved
+            track_id = find_by_tags(&store, &entry).await?;
+        }
+        match track_id {
+            Some(id) => track_ids.push(id),
+            None => unmatched.push(entry.location),
+        }
+    }
+
+    let title = title.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|s| s.to_string_lossy().into())
+            .unwrap_or_default()
+    });
+    let playlist = store
+        .create_playlist_from_tracks(&title, &track_ids)
+        .await?;
+    Ok(PlaylistImport {
+        playlist,
+        matched: track_ids.len() as u32,
+        unmatched,
+    })
+}
+
+/// Finds the track in the library an entry's tags describe. Titles without
+/// an artist are often written as `<artist> - <title>`, so each way of
+/// splitting them is tried too.
+async fn find_by_tags(store: &Store, entry: &FileEntry) -> Result<Option<String>> {
+    let title = match &entry.title {
+        Some(title) => title,
+        None => return Ok(None),
+    };
+    let album = entry.album.as_deref();
+    let found = store
+        .find_track(title, entry.artist.as_deref(), album)
+        .await?;
+    if found.is_some() || entry.artist.is_some() {
+        return Ok(found);
+    }
+    for (i, separator) in title.match_indices(" - ") {
+        let (artist, title) = (&title[..i], &title[i + separator.len()..]);
+        if let Some(id) = store.find_track(title, Some(artist), album).await? {
+            return Ok(Some(id));
+        }
+    }
+    Ok(None)
+}
+
+/// Writes a playlist out to `path`, in the format its extension names. With
+/// `relative`, tracks are written relative to the playlist file where
+/// possible.
+#[tauri::command]
+pub async fn export_playlist(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+    path: PathBuf,
+    relative: bool,
+) -> Result<()> {
+    let playlist = store
+        .get_playlist(playlist_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("playlist", playlist_id.to_string()))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let tracks = match &playlist.rules {
+        Some(rules) => store.get_smart_tracks(rules).await?,
+        None => store
+            .get_playlist_entries(playlist_id)
+            .await?
+            .into_iter()
+            .map(|entry| entry.track)
+            .collect(),
+    };
+    let entries: Vec<FileEntry> = tracks
+        .into_iter()
+        .map(|track| {
+            let location = if relative {
+                relative_path(&track.path, base_dir).unwrap_or(track.path)
+            } else {
+                track.path
+            };
+            let metadata = track.metadata;
+            FileEntry {
+                location: location.to_string_lossy().into(),
+                title: Some(metadata.title),
+                artist: Some(metadata.song_artist.unwrap_or(metadata.artist)),
+                album: Some(metadata.album).filter(|a| !a.is_empty()),
+                duration: Some(track.duration),
+            }
+        })
+        .collect();
+    tauri::async_runtime::spawn_blocking(move || playlist_file::write(&path, &entries)).await?
+}
+
+#[tauri::command]
+pub async fn create_smart_playlist(
+    store: tauri::State<'_, Store>,
+    title: String,
+    rules: SmartRules,
+) -> Result<Playlist> {
+    store.create_smart_playlist(&title, &rules).await
+}
+
+#[tauri::command]
+pub async fn set_playlist_rules(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+    rules: SmartRules,
+) -> Result<()> {
+    store.set_playlist_rules(playlist_id, &rules).await
+}
+
+#[tauri::command]
+pub async fn get_smart_playlist_tracks(
+    store: tauri::State<'_, Store>,
+    playlist_id: i64,
+) -> Result<Vec<Track>> {
+    let rules = store
+        .get_playlist(playlist_id)
+        .await?
+        .and_then(|p| p.rules)
+        .ok_or_else(|| Error::NotFound("smart playlist", playlist_id.to_string()))?;
+    store.get_smart_tracks(&rules).await
+}
+
+/// Returns the tracks that rules would match, for trying them out before
+/// they're saved.
+#[tauri::command]
+pub async fn preview_smart_playlist(
+    store: tauri::State<'_, Store>,
+    rules: SmartRules,
+) -> Result<Vec<Track>> {
+    store.get_smart_tracks(&rules).await
+}
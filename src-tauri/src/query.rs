@@ -0,0 +1,687 @@
+//! Parses the query syntax used to filter tracks, such as
+//! `artist:"Radiohead" year:>=2000 -live duration:<5m`, into the same rules
+//! smart playlists use.
+//!
+//! A query is a list of terms, all of which have to match. Terms are either
+//! words, which match the title, artist or album, or `field:value` filters.
+//! A term can be negated with `-`, terms joined with `OR`, and either grouped
+//! with brackets. Values with spaces in them go in double quotes.
+
+use crate::models::{NumberField, NumberOp, Rule, TextField, TextOp};
+use crate::{Error, Result};
+
+/// Parses a query into the rule it stands for. An empty query matches every
+/// track.
+pub fn parse(query: &str) -> Result<Rule> {
+    let mut parser = Parser {
+        chars: query.chars().collect(),
+        pos: 0,
+    };
+    let rule = parser.or_expr()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(rule),
+        Some(')') => Err(parser.error("Unmatched )")),
+        Some(_) => Err(parser.error("Unexpected text")),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn or_expr(&mut self) -> Result<Rule> {
+        let mut rules = vec![self.and_expr()?];
+        while self.eat_keyword("OR") {
+            if rules.len() == 1 && is_empty(&rules[0]) {
+                return Err(self.error_at(self.pos - 2, "Expected a term before OR"));
+            }
+            let start = self.pos;
+            let rule = self.and_expr()?;
+            if is_empty(&rule) {
+                return Err(self.error_at(start, "Expected a term after OR"));
+            }
+            rules.push(rule);
+        }
+        Ok(collapse(rules, |rules| Rule::Any { rules }))
+    }
+
+    fn and_expr(&mut self) -> Result<Rule> {
+        let mut rules = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(')') => break,
+                _ if self.at_keyword("OR") => break,
+                _ => rules.push(self.unary()?),
+            }
+        }
+        Ok(collapse(rules, |rules| Rule::All { rules }))
+    }
+
+    fn unary(&mut self) -> Result<Rule> {
+        if self.peek() == Some('-') {
+            let start = self.pos;
+            self.pos += 1;
+            return match self.peek() {
+                Some(c) if !c.is_whitespace() && c != ')' => Ok(Rule::Not {
+                    rule: Box::new(self.unary()?),
+                }),
+                _ => Err(self.error_at(start, "Expected a term after -")),
+            };
+        }
+        if self.peek() == Some('(') {
+            let start = self.pos;
+            self.pos += 1;
+            let rule = self.or_expr()?;
+            self.skip_whitespace();
+            if self.peek() != Some(')') {
+                return Err(self.error_at(start, "Unmatched ("));
+            }
+            self.pos += 1;
+            return Ok(rule);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Rule> {
+        let start = self.pos;
+        if self.peek() == Some('"') {
+            let word = self.quoted()?;
+            return Ok(word_rule(word));
+        }
+
+        let word = self.bare();
+        if self.peek() != Some(':') {
+            return Ok(word_rule(word));
+        }
+        if word.is_empty() {
+            return Err(self.error_at(start, "Expected a field name before :"));
+        }
+        self.pos += 1;
+        let field = word.to_lowercase();
+        let value_start = self.pos;
+        // Operators can go outside the quotes, as in `title:="Creep"`
+        let mut value = self.bare_value();
+        if self.peek() == Some('"') {
+            value.push_str(&self.quoted()?);
+        }
+        if value.is_empty() {
+            return Err(self.error_at(value_start, format!("Expected a value for {}", field)));
+        }
+
+        let rule = match field.as_str() {
+            "title" => text_rule(TextField::Title, &value),
+            "artist" => text_rule(TextField::Artist, &value),
+            "album" => text_rule(TextField::Album, &value),
+            "genre" => text_rule(TextField::Genre, &value),
+            "year" => number_rule(NumberField::Year, &value, parse_int),
+            "duration" | "length" => number_rule(NumberField::Duration, &value, parse_duration),
+            "track" => number_rule(NumberField::TrackNumber, &value, parse_int),
+            "plays" => number_rule(NumberField::PlayCount, &value, parse_int),
+            "skips" => number_rule(NumberField::SkipCount, &value, parse_int),
+            "rating" => number_rule(NumberField::Rating, &value, parse_rating),
+            "added" => within_rule(&value).map(|days| Rule::AddedWithin { days }),
+            "played" => within_rule(&value).map(|days| Rule::PlayedWithin { days }),
+            "loved" => parse_bool(&value).map(|loved| Rule::Loved { loved }),
+            _ => return Err(self.error_at(start, format!("Unknown field {}", field))),
+        };
+        rule.map_err(|message| self.error_at(value_start, message))
+    }
+
+    /// Reads a word up to the next space, bracket, colon or quote.
+    fn bare(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' || c == ':' || c == '"' {
+                break;
+            }
+            word.push(c);
+            self.pos += 1;
+        }
+        word
+    }
+
+    /// Reads a value up to the next space, bracket or quote. Unlike words,
+    /// values can have colons in them, as in `duration:3:30`.
+    fn bare_value(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                break;
+            }
+            value.push(c);
+            self.pos += 1;
+        }
+        value
+    }
+
+    /// Reads a value in double quotes, where `\"` is a quote and `\\` a
+    /// backslash.
+    fn quoted(&mut self) -> Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error_at(start, "Unmatched \"")),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some('\\') if matches!(self.chars.get(self.pos + 1), Some('"' | '\\')) => {
+                    value.push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        let end = self.pos + keyword.len();
+        end <= self.chars.len()
+            && self.chars[self.pos..end]
+                .iter()
+                .copied()
+                .eq(keyword.chars())
+            && self
+                .chars
+                .get(end)
+                .map_or(true, |c| c.is_whitespace() || *c == '(')
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += keyword.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        self.error_at(self.pos, message)
+    }
+
+    /// Positions in errors count characters from 1, as people would.
+    fn error_at(&self, pos: usize, message: impl Into<String>) -> Error {
+        Error::Query(pos + 1, message.into())
+    }
+}
+
+fn is_empty(rule: &Rule) -> bool {
+    matches!(rule, Rule::All { rules } if rules.is_empty())
+}
+
+/// Joins rules with `join`, unless there's only one of them.
+fn collapse(mut rules: Vec<Rule>, join: impl FnOnce(Vec<Rule>) -> Rule) -> Rule {
+    if rules.len() == 1 {
+        rules.remove(0)
+    } else {
+        join(rules)
+    }
+}
+
+/// A word on its own can be in the title, artist or album.
+fn word_rule(word: String) -> Rule {
+    let fields = [TextField::Title, TextField::Artist, TextField::Album];
+    Rule::Any {
+        rules: fields
+            .iter()
+            .map(|field| Rule::Text {
+                field: *field,
+                op: TextOp::Contains,
+                value: word.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// `field:value` matches when the field contains the value, `field:=value`
+/// when it's exactly the value, and `field:^value` when it starts with it.
+fn text_rule(field: TextField, value: &str) -> std::result::Result<Rule, String> {
+    let (op, value) = if let Some(v) = value.strip_prefix('=') {
+        (TextOp::Is, v)
+    } else if let Some(v) = value.strip_prefix('^') {
+        (TextOp::StartsWith, v)
+    } else {
+        (TextOp::Contains, value)
+    };
+    Ok(Rule::Text {
+        field,
+        op,
+        value: value.into(),
+    })
+}
+
+/// Numbers can be compared with `=`, `!=`, `>`, `>=`, `<` or `<=`, or given
+/// as an inclusive range like `1990..1999`.
+fn number_rule(
+    field: NumberField,
+    value: &str,
+    parse: fn(&str) -> std::result::Result<i64, String>,
+) -> std::result::Result<Rule, String> {
+    if let Some((min, max)) = value.split_once("..") {
+        return Ok(Rule::Between {
+            field,
+            min: parse(min)?,
+            max: parse(max)?,
+        });
+    }
+    let ops = [
+        (">=", NumberOp::AtLeast),
+        ("<=", NumberOp::AtMost),
+        ("!=", NumberOp::IsNot),
+        (">", NumberOp::GreaterThan),
+        ("<", NumberOp::LessThan),
+        ("=", NumberOp::Is),
+    ];
+    let (op, value) = ops
+        .iter()
+        .find_map(|(prefix, op)| value.strip_prefix(prefix).map(|v| (*op, v)))
+        .unwrap_or((NumberOp::Is, value));
+    Ok(Rule::Number {
+        field,
+        op,
+        value: parse(value)?,
+    })
+}
+
+fn parse_int(value: &str) -> std::result::Result<i64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Expected a number, not {:?}", value))
+}
+
+fn parse_rating(value: &str) -> std::result::Result<i64, String> {
+    match parse_int(value)? {
+        n @ 0..=5 => Ok(n),
+        _ => Err("Ratings go from 0 to 5 stars".into()),
+    }
+}
+
+/// Reads a duration in seconds, written as `90`, `90s`, `5m`, `1h`, `3m30s`,
+/// `3:30` or `1:02:30`.
+fn parse_duration(value: &str) -> std::result::Result<i64, String> {
+    let invalid = || format!("Expected a duration like 3m30s or 3:30, not {:?}", value);
+    if value.contains(':') {
+        let parts: Vec<&str> = value.split(':').collect();
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        let mut seconds: i64 = 0;
+        for part in parts {
+            let n = parse_digits(part).ok_or_else(invalid)?;
+            seconds = seconds
+                .checked_mul(60)
+                .and_then(|s| s.checked_add(n))
+                .ok_or_else(invalid)?;
+        }
+        return Ok(seconds);
+    }
+
+    let mut seconds: i64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        seconds = parse_digits(&number)
+            .and_then(|n| n.checked_mul(unit))
+            .and_then(|n| seconds.checked_add(n))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds = parse_digits(&number)
+            .and_then(|n| seconds.checked_add(n))
+            .ok_or_else(invalid)?;
+    } else if value.is_empty() {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
+/// Reads a number written only in digits, with no sign, or `None` if it
+/// isn't one or is too big.
+fn parse_digits(s: &str) -> Option<i64> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Reads how far back `added:` and `played:` look, written as `30`, `30d`,
+/// `2w` or `1y`, optionally after a `<` or `<=`.
+fn within_rule(value: &str) -> std::result::Result<u32, String> {
+    let value = value
+        .strip_prefix("<=")
+        .or_else(|| value.strip_prefix('<'))
+        .unwrap_or(value);
+    let invalid = || {
+        format!(
+            "Expected a number of days like 30d, 2w or 1y, not {:?}",
+            value
+        )
+    };
+    let (number, days) = match value.char_indices().last() {
+        Some((i, 'd')) => (&value[..i], 1),
+        Some((i, 'w')) => (&value[..i], 7),
+        Some((i, 'y')) => (&value[..i], 365),
+        _ => (value, 1),
+    };
+    let n: u32 = number.parse().map_err(|_| invalid())?;
+    n.checked_mul(days).ok_or_else(invalid)
+}
+
+fn parse_bool(value: &str) -> std::result::Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Err(format!("Expected yes or no, not {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(field: TextField, op: TextOp, value: &str) -> Rule {
+        Rule::Text {
+            field,
+            op,
+            value: value.into(),
+        }
+    }
+
+    fn number(field: NumberField, op: NumberOp, value: i64) -> Rule {
+        Rule::Number { field, op, value }
+    }
+
+    fn word(value: &str) -> Rule {
+        word_rule(value.into())
+    }
+
+    fn all(rules: Vec<Rule>) -> Rule {
+        Rule::All { rules }
+    }
+
+    fn any(rules: Vec<Rule>) -> Rule {
+        Rule::Any { rules }
+    }
+
+    fn not(rule: Rule) -> Rule {
+        Rule::Not {
+            rule: Box::new(rule),
+        }
+    }
+
+    /// Where the error was, counting from 1
+    fn error_pos(query: &str) -> usize {
+        match parse(query) {
+            Err(Error::Query(pos, _)) => pos,
+            other => panic!("{:?} parsed as {:?}", query, other),
+        }
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(parse("").unwrap(), all(vec![]));
+        assert_eq!(parse("   ").unwrap(), all(vec![]));
+    }
+
+    #[test]
+    fn words_match_title_artist_or_album() {
+        assert_eq!(
+            parse("creep").unwrap(),
+            any(vec![
+                text(TextField::Title, TextOp::Contains, "creep"),
+                text(TextField::Artist, TextOp::Contains, "creep"),
+                text(TextField::Album, TextOp::Contains, "creep"),
+            ])
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("a b OR c").unwrap(),
+            any(vec![all(vec![word("a"), word("b")]), word("c")])
+        );
+        assert_eq!(
+            parse("a (b OR c)").unwrap(),
+            all(vec![word("a"), any(vec![word("b"), word("c")])])
+        );
+        assert_eq!(
+            parse("a OR b OR c").unwrap(),
+            any(vec![word("a"), word("b"), word("c")])
+        );
+        // Only upper case OR is a keyword
+        assert_eq!(
+            parse("a or b").unwrap(),
+            all(vec![word("a"), word("or"), word("b")])
+        );
+        assert_eq!(parse("((a))").unwrap(), word("a"));
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(parse("-live").unwrap(), not(word("live")));
+        assert_eq!(
+            parse("a -(b OR c)").unwrap(),
+            all(vec![word("a"), not(any(vec![word("b"), word("c")]))])
+        );
+        assert_eq!(parse("--a").unwrap(), not(not(word("a"))));
+        assert_eq!(
+            parse("-genre:jazz").unwrap(),
+            not(text(TextField::Genre, TextOp::Contains, "jazz"))
+        );
+        // Only at the start of a term
+        assert_eq!(parse("half-life").unwrap(), word("half-life"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            parse("\"paranoid android\"").unwrap(),
+            word("paranoid android")
+        );
+        assert_eq!(
+            parse(r#""say \"hi\" \\ bye""#).unwrap(),
+            word(r#"say "hi" \ bye"#)
+        );
+        assert_eq!(
+            parse("artist:\"Sigur Rós\"").unwrap(),
+            text(TextField::Artist, TextOp::Contains, "Sigur Rós")
+        );
+        assert_eq!(
+            parse("title:=\"Creep\"").unwrap(),
+            text(TextField::Title, TextOp::Is, "Creep")
+        );
+        // Keywords and syntax mean nothing in quotes
+        assert_eq!(parse("\"a OR -(b)\"").unwrap(), word("a OR -(b)"));
+    }
+
+    #[test]
+    fn text_fields_and_operators() {
+        let fields = [
+            ("title", TextField::Title),
+            ("artist", TextField::Artist),
+            ("album", TextField::Album),
+            ("genre", TextField::Genre),
+        ];
+        let ops = [
+            ("", TextOp::Contains),
+            ("=", TextOp::Is),
+            ("^", TextOp::StartsWith),
+        ];
+        for (name, field) in fields {
+            for (prefix, op) in ops {
+                let query = format!("{}:{}rock", name, prefix);
+                assert_eq!(parse(&query).unwrap(), text(field, op, "rock"), "{}", query);
+            }
+        }
+        // Field names ignore case
+        assert_eq!(
+            parse("Artist:björk").unwrap(),
+            text(TextField::Artist, TextOp::Contains, "björk")
+        );
+    }
+
+    #[test]
+    fn number_fields_and_operators() {
+        let fields = [
+            ("year", NumberField::Year),
+            ("duration", NumberField::Duration),
+            ("length", NumberField::Duration),
+            ("track", NumberField::TrackNumber),
+            ("plays", NumberField::PlayCount),
+            ("skips", NumberField::SkipCount),
+            ("rating", NumberField::Rating),
+        ];
+        let ops = [
+            ("", NumberOp::Is),
+            ("=", NumberOp::Is),
+            ("!=", NumberOp::IsNot),
+            (">", NumberOp::GreaterThan),
+            (">=", NumberOp::AtLeast),
+            ("<", NumberOp::LessThan),
+            ("<=", NumberOp::AtMost),
+        ];
+        for (name, field) in fields {
+            for (prefix, op) in ops {
+                let query = format!("{}:{}3", name, prefix);
+                assert_eq!(parse(&query).unwrap(), number(field, op, 3), "{}", query);
+            }
+            let query = format!("{}:1..4", name);
+            assert_eq!(
+                parse(&query).unwrap(),
+                Rule::Between {
+                    field,
+                    min: 1,
+                    max: 4
+                },
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [
+            ("90", 90),
+            ("90s", 90),
+            ("5m", 300),
+            ("1h", 3600),
+            ("3m30s", 210),
+            ("3M30S", 210),
+            ("3:30", 210),
+            ("1:02:30", 3750),
+        ];
+        for (value, seconds) in cases {
+            let query = format!("duration:<{}", value);
+            assert_eq!(
+                parse(&query).unwrap(),
+                number(NumberField::Duration, NumberOp::LessThan, seconds),
+                "{}",
+                query
+            );
+        }
+        assert_eq!(
+            parse("duration:3:00..5:00 live").unwrap(),
+            all(vec![
+                Rule::Between {
+                    field: NumberField::Duration,
+                    min: 180,
+                    max: 300
+                },
+                word("live"),
+            ])
+        );
+    }
+
+    #[test]
+    fn added_played_and_loved() {
+        let cases = [("30", 30), ("30d", 30), ("<2w", 14), ("<=1y", 365)];
+        for (value, days) in cases {
+            assert_eq!(
+                parse(&format!("added:{}", value)).unwrap(),
+                Rule::AddedWithin { days }
+            );
+            assert_eq!(
+                parse(&format!("played:{}", value)).unwrap(),
+                Rule::PlayedWithin { days }
+            );
+        }
+        for (value, loved) in [("yes", true), ("True", true), ("0", false), ("no", false)] {
+            assert_eq!(
+                parse(&format!("loved:{}", value)).unwrap(),
+                Rule::Loved { loved }
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_queries() {
+        assert_eq!(error_pos("(a"), 1);
+        assert_eq!(error_pos("a)"), 2);
+        assert_eq!(error_pos("a -"), 3);
+        assert_eq!(error_pos("-)"), 1);
+        assert_eq!(error_pos("OR a"), 1);
+        assert_eq!(error_pos("a OR"), 5);
+        assert_eq!(error_pos("a OR OR b"), 5);
+        assert_eq!(error_pos("\"unterminated"), 1);
+        assert_eq!(error_pos(":x"), 1);
+        assert_eq!(error_pos("colour:red"), 1);
+        assert_eq!(error_pos("year:"), 6);
+        assert_eq!(error_pos("a year:abc"), 8);
+        assert_eq!(error_pos("year:1990.."), 6);
+        assert_eq!(error_pos("rating:6"), 8);
+        assert_eq!(error_pos("loved:maybe"), 7);
+        assert_eq!(error_pos("added:soon"), 7);
+        assert_eq!(error_pos("added:99999999999y"), 7);
+        for value in ["", "3x", "m", "-5", "3:-30", "3::30", "1:2:3:4", "+5s"] {
+            let query = format!("duration:{}", value);
+            assert_eq!(error_pos(&query), 10, "{}", query);
+        }
+    }
+
+    #[test]
+    fn huge_durations_are_errors() {
+        for value in [
+            "99999999999999999999",
+            "9999999999999999999h",
+            "4000000000000000000m",
+            "9223372036854775807s1s",
+            "9999999999999999999:00",
+            "3074457345618258602:00:00",
+        ] {
+            let query = format!("duration:{}", value);
+            assert_eq!(error_pos(&query), 10, "{}", query);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Turns the rules of smart playlists into SQL over the `track` table, joined
+//! with `album`.
+
+use crate::models::{
+    NumberField, NumberOp, Rule, SmartRules, SmartSort, SortField, TextField, TextOp,
+};
+
+/// A value bound to one of the `?`s in the generated SQL.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Param {
+    Text(String),
+    Int(i64),
+}
+
+/// Returns the `WHERE`, `ORDER BY` and `LIMIT` clauses selecting the tracks
+/// that `rules` match, along with the values to bind to them in order.
+pub fn to_sql(rules: &SmartRules) -> (String, Vec<Param>) {
+    let (condition, params) = rule_to_sql(&rules.rule);
+    let mut sql = format!("WHERE {}", condition);
+    if let Some(sort) = &rules.sort {
+        sql.push_str(&format!(" ORDER BY {}", sort_sql(sort)));
+    }
+    if let Some(limit) = rules.limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+    (sql, params)
+}
+
+/// Returns the condition a track has to meet to match `rule`, along with the
+/// values to bind to it in order.
+pub fn rule_to_sql(rule: &Rule) -> (String, Vec<Param>) {
+    let mut params = vec![];
+    let sql = rule_sql(rule, &mut params);
+    (sql, params)
+}
+
+/// Returns what to put after `ORDER BY` to sort tracks.
+pub fn sort_sql(sort: &SmartSort) -> String {
+    let column = match sort.field {
+        SortField::Title => "track.sort_title",
+        SortField::Artist => "track.sort_artist",
+        SortField::Album => "track.sort_album",
+        SortField::Year => "track.year",
+        SortField::Duration => "track.duration",
+        SortField::AddedAt => "track.added_at",
+        SortField::PlayCount => "track.play_count",
+        SortField::LastPlayed => "track.last_played",
+        SortField::Rating => "track.rating",
+        SortField::Random => "RANDOM()",
+    };
+    let order = if sort.descending { "DESC" } else { "ASC" };
+    format!("{} {}", column, order)
+}
+
+fn rule_sql(rule: &Rule, params: &mut Vec<Param>) -> String {
+    match rule {
+        Rule::All { rules } => join(rules, " AND ", "1", params),
+        Rule::Any { rules } => join(rules, " OR ", "0", params),
+        Rule::Text { field, op, value } => match field {
+            // A track matches on its album artist or anyone credited on it, or
+            // none of them for the negative comparisons
+            TextField::Artist => {
+                let (joiner, exists, credit_op) = match op {
+                    TextOp::IsNot => (" AND ", "NOT EXISTS", TextOp::Is),
+                    TextOp::NotContains => (" AND ", "NOT EXISTS", TextOp::Contains),
+                    op => (" OR ", "EXISTS", *op),
+                };
+                let album_artist = text_sql("track.artist", *op, value, params);
+                let credited = text_sql("artist_credit.artist", credit_op, value, params);
+                format!(
+                    "({}{}{} (SELECT 1 FROM artist_credit WHERE artist_credit.track_id = track.id AND {}))",
+                    album_artist, joiner, exists, credited
+                )
+            }
+            TextField::Title => text_sql("track.title", *op, value, params),
+            TextField::Album => text_sql("album.title", *op, value, params),
+            // A track matches on any of its genres, or none of them for the
+            // negative comparisons
+            TextField::Genre => {
+                let (exists, op) = match op {
+                    TextOp::IsNot => ("NOT EXISTS", TextOp::Is),
+                    TextOp::NotContains => ("NOT EXISTS", TextOp::Contains),
+                    op => ("EXISTS", *op),
+                };
+                format!(
+                    "{} (SELECT 1 FROM track_genre WHERE track_genre.track_id = track.id AND {})",
+                    exists,
+                    text_sql("track_genre.genre", op, value, params)
+                )
+            }
+        },
+        Rule::Number { field, op, value } => {
+            let op = match op {
+                NumberOp::Is => "=",
+                NumberOp::IsNot => "IS NOT",
+                NumberOp::GreaterThan => ">",
+                NumberOp::AtLeast => ">=",
+                NumberOp::LessThan => "<",
+                NumberOp::AtMost => "<=",
+            };
+            params.push(Param::Int(*value));
+            format!("{} {} ?", number_column(*field), op)
+        }
+        Rule::Between { field, min, max } => {
+            params.push(Param::Int(*min));
+            params.push(Param::Int(*max));
+            format!("{} BETWEEN ? AND ?", number_column(*field))
+        }
+        Rule::AddedWithin { days } => {
+            params.push(Param::Int(*days as i64 * 24 * 60 * 60));
+            "track.added_at >= CAST(strftime('%s', 'now') AS INTEGER) - ?".into()
+        }
+        Rule::PlayedWithin { days } => {
+            params.push(Param::Int(*days as i64 * 24 * 60 * 60));
+            "track.last_played >= CAST(strftime('%s', 'now') AS INTEGER) - ?".into()
+        }
+        Rule::Loved { loved } => {
+            params.push(Param::Int(*loved as i64));
+            "track.loved = ?".into()
+        }
+        Rule::Not { rule } => format!("NOT COALESCE({}, 0)", rule_sql(rule, params)),
+    }
+}
+
+fn join(rules: &[Rule], joiner: &str, empty: &str, params: &mut Vec<Param>) -> String {
+    if rules.is_empty() {
+        return empty.into();
+    }
+    let parts: Vec<String> = rules.iter().map(|r| rule_sql(r, params)).collect();
+    format!("({})", parts.join(joiner))
+}
+
+fn text_sql(column: &str, op: TextOp, value: &str, params: &mut Vec<Param>) -> String {
+    // LIKE already ignores case, but its wildcards in the value need escaping
+    let pattern = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let (sql, param) = match op {
+        TextOp::Is => (format!("{} = ? COLLATE NOCASE", column), value.into()),
+        // Missing values count as not matching, rather than as unknown
+        TextOp::IsNot => (
+            format!("COALESCE({}, '') != ? COLLATE NOCASE", column),
+            value.into(),
+        ),
+        TextOp::Contains => (
+            format!("{} LIKE ? ESCAPE '\\'", column),
+            format!("%{}%", pattern),
+        ),
+        TextOp::NotContains => (
+            format!("COALESCE({}, '') NOT LIKE ? ESCAPE '\\'", column),
+            format!("%{}%", pattern),
+        ),
+        TextOp::StartsWith => (
+            format!("{} LIKE ? ESCAPE '\\'", column),
+            format!("{}%", pattern),
+        ),
+    };
+    params.push(Param::Text(param));
+    sql
+}
+
+fn number_column(field: NumberField) -> &'static str {
+    match field {
+        NumberField::Year => "track.year",
+        NumberField::Duration => "track.duration",
+        NumberField::TrackNumber => "track.track_number",
+        NumberField::PlayCount => "track.play_count",
+        NumberField::SkipCount => "track.skip_count",
+        // Unrated tracks count as having no stars
+        NumberField::Rating => "COALESCE(track.rating, 0)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(field: TextField, op: TextOp, value: &str) -> Rule {
+        Rule::Text {
+            field,
+            op,
+            value: value.into(),
+        }
+    }
+
+    fn number(field: NumberField, op: NumberOp, value: i64) -> Rule {
+        Rule::Number { field, op, value }
+    }
+
+    #[test]
+    fn empty_groups_match_everything_or_nothing() {
+        assert_eq!(
+            rule_to_sql(&Rule::All { rules: vec![] }),
+            ("1".into(), vec![])
+        );
+        assert_eq!(
+            rule_to_sql(&Rule::Any { rules: vec![] }),
+            ("0".into(), vec![])
+        );
+    }
+
+    #[test]
+    fn joins_groups_with_params_in_order() {
+        let rule = Rule::Any {
+            rules: vec![
+                number(NumberField::Year, NumberOp::AtLeast, 1990),
+                Rule::All {
+                    rules: vec![
+                        text(TextField::Title, TextOp::Is, "Song"),
+                        Rule::Loved { loved: true },
+                    ],
+                },
+            ],
+        };
+        let (sql, params) = rule_to_sql(&rule);
+        assert_eq!(
+            sql,
+            "(track.year >= ? OR (track.title = ? COLLATE NOCASE AND track.loved = ?))"
+        );
+        assert_eq!(
+            params,
+            [Param::Int(1990), Param::Text("Song".into()), Param::Int(1)]
+        );
+    }
+
+    #[test]
+    fn not_matches_when_the_rule_cant_tell() {
+        let rule = Rule::Not {
+            rule: Box::new(number(NumberField::Year, NumberOp::GreaterThan, 2000)),
+        };
+        let (sql, params) = rule_to_sql(&rule);
+        assert_eq!(sql, "NOT COALESCE(track.year > ?, 0)");
+        assert_eq!(params, [Param::Int(2000)]);
+    }
+
+    #[test]
+    fn unrated_tracks_have_no_stars() {
+        let rule = number(NumberField::Rating, NumberOp::LessThan, 3);
+        let (sql, _) = rule_to_sql(&rule);
+        assert_eq!(sql, "COALESCE(track.rating, 0) < ?");
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        let rule = text(TextField::Album, TextOp::Contains, r"100% a_b\c");
+        let (sql, params) = rule_to_sql(&rule);
+        assert_eq!(sql, r"album.title LIKE ? ESCAPE '\'");
+        assert_eq!(params, [Param::Text(r"%100\% a\_b\\c%".into())]);
+
+        let rule = text(TextField::Title, TextOp::StartsWith, "50%");
+        let (_, params) = rule_to_sql(&rule);
+        assert_eq!(params, [Param::Text(r"50\%%".into())]);
+    }
+
+    #[test]
+    fn negative_text_rules_match_missing_values() {
+        let rule = text(TextField::Title, TextOp::NotContains, "live");
+        let (sql, params) = rule_to_sql(&rule);
+        assert_eq!(sql, r"COALESCE(track.title, '') NOT LIKE ? ESCAPE '\'");
+        assert_eq!(params, [Param::Text("%live%".into())]);
+    }
+
+    #[test]
+    fn artists_match_on_credits_too() {
+        let rule = text(TextField::Artist, TextOp::Is, "A");
+        let (sql, params) = rule_to_sql(&rule);
+        assert_eq!(
+            sql,
+            "(track.artist = ? COLLATE NOCASE OR EXISTS (SELECT 1 FROM artist_credit WHERE artist_credit.track_id = track.id AND artist_credit.artist = ? COLLATE NOCASE))"
+        );
+        assert_eq!(params, [Param::Text("A".into()), Param::Text("A".into())]);
+
+        let rule = text(TextField::Artist, TextOp::IsNot, "A");
+        let (sql, _) = rule_to_sql(&rule);
+        assert_eq!(
+            sql,
+            "(COALESCE(track.artist, '') != ? COLLATE NOCASE AND NOT EXISTS (SELECT 1 FROM artist_credit WHERE artist_credit.track_id = track.id AND artist_credit.artist = ? COLLATE NOCASE))"
+        );
+    }
+
+    #[test]
+    fn genres_match_on_any_of_a_tracks_genres() {
+        let rule = text(TextField::Genre, TextOp::NotContains, "rock");
+        let (sql, _) = rule_to_sql(&rule);
+        assert_eq!(
+            sql,
+            r"NOT EXISTS (SELECT 1 FROM track_genre WHERE track_genre.track_id = track.id AND track_genre.genre LIKE ? ESCAPE '\')"
+        );
+    }
+
+    #[test]
+    fn adds_sort_and_limit() {
+        let rules = SmartRules {
+            rule: Rule::AddedWithin { days: 2 },
+            limit: Some(25),
+            sort: Some(SmartSort {
+                field: SortField::Artist,
+                descending: true,
+            }),
+        };
+        let (sql, params) = to_sql(&rules);
+        assert_eq!(
+            sql,
+            "WHERE track.added_at >= CAST(strftime('%s', 'now') AS INTEGER) - ? ORDER BY track.sort_artist DESC LIMIT 25"
+        );
+        assert_eq!(params, [Param::Int(2 * 24 * 60 * 60)]);
+    }
+}
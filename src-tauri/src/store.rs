@@ -1,12 +1,39 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::query::Query;
+use sqlx::sqlite::{
+    Sqlite, SqliteArguments, SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions,
+    SqliteRow,
+};
+use sqlx::{Executor, Row};
 
-use crate::models::{Album, Artist, Metadata, Playlist, Track};
-use crate::{create_data_dir, Result};
+use crate::collation::{natural_cmp, natural_key, NATURAL};
+use crate::models::{
+    Album, Artist, ArtistCredit, ArtistRole, CleanupSummary, DuplicateFile, FileInfo, Genre,
+    LibraryRoot, Metadata, Page, Playlist, PlaylistEntry, Rule, ScanError, ScannedTrack,
+    SearchResults, SmartRules, SmartSort, SortField, Track, TrackFile, TrackStats, VARIOUS_ARTISTS,
+};
+use crate::smart_playlist::{self, Param};
+use crate::{create_data_dir, Error, Result};
+
+/// What to select to read tracks with `track_from_row`, from `track` joined
+/// with `album`
+const TRACK_COLUMNS: &str = "track.*, album.title AS album_title, (SELECT group_concat(genre, ';') FROM track_genre WHERE track_genre.track_id = track.id) AS genres, (SELECT group_concat(role || ':' || artist, char(31)) FROM artist_credit WHERE artist_credit.track_id = track.id) AS credits";
+
+/// How tracks are sorted unless asked otherwise
+const DEFAULT_TRACK_ORDER: &str =
+    "track.sort_artist, track.sort_album, track.cd_number, track.track_number";
+
+/// How the tracks on an album are sorted unless asked otherwise
+const ALBUM_TRACK_ORDER: &str = "track.cd_number, track.track_number, track.sort_title";
+
+/// Lists of ids are bound in batches of this many, keeping well under
+/// SQLite's limit on parameters in a statement
+const MAX_IDS_PER_STATEMENT: usize = 500;
 
 pub struct Store {
     db: SqlitePool,
@@ -22,8 +49,14 @@ impl Store {
         } else {
             format!("sqlite://{}", db_path.to_string_lossy())
         };
+        Self::open(&db_url).await
+    }
 
-        let options = SqliteConnectOptions::from_str(&db_url)?.create_if_missing(true);
+    /// Opens the database at `db_url`, bringing its schema up to date.
+    pub async fn open(db_url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(db_url)?
+            .create_if_missing(true)
+            .collation(NATURAL, natural_cmp);
 
         let pool = SqlitePoolOptions::new().connect_with(options).await?;
 
@@ -31,337 +64,1838 @@ impl Store {
             .run(&pool)
             .await
             .map_err(sqlx::Error::from)?;
+        fill_sort_keys(&pool).await?;
 
         Ok(Self { db: pool })
     }
 
-    pub async fn remove_track_from_playlist(&self, track_id: &str, playlist: &str) -> Result<u64> {
-        let entry = sqlx::query!(
-            r#"DELETE FROM playlist_track WHERE playlist = ? AND track_id = ? RETURNING position as "position!""#,
-            playlist,
-            track_id,
+    pub async fn create_playlist(&self, title: &str) -> Result<Playlist> {
+        let id = sqlx::query!("INSERT INTO playlist (title) VALUES (?)", title)
+            .execute(&self.db)
+            .await?
+            .last_insert_rowid();
+        Ok(Playlist {
+            id,
+            title: title.into(),
+            rules: None,
+        })
+    }
+
+    /// Creates a playlist holding `track_ids` in order, all in one
+    /// transaction.
+    pub async fn create_playlist_from_tracks(
+        &self,
+        title: &str,
+        track_ids: &[String],
+    ) -> Result<Playlist> {
+        let mut tx = self.db.begin().await?;
+        let id = sqlx::query!("INSERT INTO playlist (title) VALUES (?)", title)
+            .execute(&mut tx)
+            .await?
+            .last_insert_rowid();
+        for (position, track_id) in track_ids.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO playlist_track (playlist_id, track_id, position) VALUES (?, ?, ?)",
+                id,
+                track_id,
+                position,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(Playlist {
+            id,
+            title: title.into(),
+            rules: None,
+        })
+    }
+
+    /// Finds a track by its tags, ignoring case, for playlist entries whose
+    /// paths don't match anything in the library. The artist can be either
+    /// the track's album artist or its song artist.
+    pub async fn find_track(
+        &self,
+        title: &str,
+        artist: Option<&str>,
+        album: Option<&str>,
+    ) -> Result<Option<String>> {
+        let res = sqlx::query!(
+            "SELECT track.id FROM track
+            JOIN album ON album.id = track.album_id
+            WHERE track.title = ?1 COLLATE NOCASE
+            AND (?2 IS NULL OR track.artist = ?2 COLLATE NOCASE OR track.song_artist = ?2 COLLATE NOCASE)
+            AND (?3 IS NULL OR album.title = ?3 COLLATE NOCASE)
+            LIMIT 1",
+            title,
+            artist,
+            album,
         )
-        .fetch_one(&self.db)
+        .fetch_optional(&self.db)
         .await?;
+        Ok(res.map(|track| track.id))
+    }
+
+    pub async fn create_smart_playlist(&self, title: &str, rules: &SmartRules) -> Result<Playlist> {
+        let json = serde_json::to_string(rules)?;
+        let id = sqlx::query!(
+            "INSERT INTO playlist (title, rules) VALUES (?, ?)",
+            title,
+            json
+        )
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid();
+        Ok(Playlist {
+            id,
+            title: title.into(),
+            rules: Some(rules.clone()),
+        })
+    }
 
+    pub async fn set_playlist_rules(&self, id: i64, rules: &SmartRules) -> Result<()> {
+        let json = serde_json::to_string(rules)?;
         let changes = sqlx::query!(
-            "UPDATE playlist_track SET position = position - 1 WHERE playlist = ? AND position > ?",
-            playlist,
-            entry.position,
+            "UPDATE playlist SET rules = ? WHERE id = ? AND rules IS NOT NULL",
+            json,
+            id
         )
         .execute(&self.db)
         .await?
         .rows_affected();
+        if changes == 0 {
+            return Err(Error::NotFound("smart playlist", id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the tracks that match a smart playlist's rules.
+    pub async fn get_smart_tracks(&self, rules: &SmartRules) -> Result<Vec<Track>> {
+        let (clauses, params) = smart_playlist::to_sql(rules);
+        let query_str = format!(
+            "SELECT {} FROM track JOIN album ON album.id = track.album_id {}",
+            TRACK_COLUMNS, clauses
+        );
+        let mut query = sqlx::query(&query_str);
+        for param in params {
+            query = match param {
+                Param::Text(s) => query.bind(s),
+                Param::Int(n) => query.bind(n),
+            };
+        }
+        let rows = query.fetch_all(&self.db).await?;
+        let res = rows
+            .iter()
+            .map(track_from_row)
+            .collect::<sqlx::Result<_>>()?;
+        Ok(res)
+    }
+
+    /// Returns a page of the tracks matching `rule`, sorted by `sort` or else
+    /// by artist, album and track number.
+    pub async fn query_tracks(
+        &self,
+        rule: &Rule,
+        sort: Option<&SmartSort>,
+        offset: u32,
+        limit: Option<u32>,
+    ) -> Result<Page<Track>> {
+        let (condition, params) = smart_playlist::rule_to_sql(rule);
+        let order = match sort {
+            Some(sort) => smart_playlist::sort_sql(sort),
+            None => DEFAULT_TRACK_ORDER.into(),
+        };
+        self.fetch_tracks(&condition, params, &order, offset, limit)
+            .await
+    }
+
+    /// Returns a page of the tracks in the library, or only those on an
+    /// album or in a genre if given one. Tracks are sorted by `sort`, or else
+    /// by artist and album, or by their place on the album.
+    pub async fn get_tracks(
+        &self,
+        album_id: Option<&str>,
+        genre: Option<&str>,
+        sort: Option<&SmartSort>,
+        offset: u32,
+        limit: Option<u32>,
+    ) -> Result<Page<Track>> {
+        let mut conditions = vec!["1"];
+        let mut params = vec![];
+        if let Some(id) = album_id {
+            conditions.push("track.album_id = ?");
+            params.push(Param::Text(id.into()));
+        }
+        if let Some(genre) = genre {
+            conditions.push("EXISTS (SELECT 1 FROM track_genre WHERE track_genre.track_id = track.id AND track_genre.genre = ?)");
+            params.push(Param::Text(genre.into()));
+        }
+        let order = match (sort, album_id) {
+            (Some(sort), _) => smart_playlist::sort_sql(sort),
+            (None, Some(_)) => ALBUM_TRACK_ORDER.into(),
+            (None, None) => DEFAULT_TRACK_ORDER.into(),
+        };
+        self.fetch_tracks(&conditions.join(" AND "), params, &order, offset, limit)
+            .await
+    }
+
+    async fn fetch_tracks(
+        &self,
+        condition: &str,
+        params: Vec<Param>,
+        order: &str,
+        offset: u32,
+        limit: Option<u32>,
+    ) -> Result<Page<Track>> {
+        let count_str = format!(
+            "SELECT COUNT(*) FROM track JOIN album ON album.id = track.album_id WHERE {}",
+            condition
+        );
+        let mut count_query = sqlx::query_scalar(&count_str);
+        for param in &params {
+            count_query = match param {
+                Param::Text(s) => count_query.bind(s),
+                Param::Int(n) => count_query.bind(n),
+            };
+        }
+        let total: i64 = count_query.fetch_one(&self.db).await?;
+
+        // The page is found first so that genres and credits are only read
+        // for the tracks on it. Ties are broken by id, so that pages don't
+        // overlap.
+        let query_str = format!(
+            "SELECT track.id FROM track JOIN album ON album.id = track.album_id WHERE {} ORDER BY {}, track.id LIMIT ? OFFSET ?",
+            condition, order
+        );
+        let mut query = sqlx::query_scalar(&query_str);
+        for param in &params {
+            query = match param {
+                Param::Text(s) => query.bind(s),
+                Param::Int(n) => query.bind(n),
+            };
+        }
+        let ids: Vec<String> = query
+            .bind(limit.map_or(-1, i64::from))
+            .bind(offset)
+            .fetch_all(&self.db)
+            .await?;
+        let items = self.get_tracks_by_id(&ids).await?;
+        Ok(Page {
+            items,
+            total: total as u32,
+        })
+    }
+
+    /// Reads the tracks with `ids`, in the same order.
+    async fn get_tracks_by_id(&self, ids: &[String]) -> Result<Vec<Track>> {
+        let mut tracks = HashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_IDS_PER_STATEMENT) {
+            let query_str = format!(
+                "SELECT {} FROM track JOIN album ON album.id = track.album_id WHERE track.id IN ({})",
+                TRACK_COLUMNS,
+                placeholders(chunk.len())
+            );
+            for row in bind_all(sqlx::query(&query_str), chunk)
+                .fetch_all(&self.db)
+                .await?
+            {
+                let track = track_from_row(&row)?;
+                tracks.insert(track.id.clone(), track);
+            }
+        }
+        Ok(ids.iter().filter_map(|id| tracks.remove(id)).collect())
+    }
+
+    pub async fn get_playlist(&self, id: i64) -> Result<Option<Playlist>> {
+        let playlist = sqlx::query!("SELECT id, title, rules FROM playlist WHERE id = ?", id)
+            .fetch_optional(&self.db)
+            .await?;
+        match playlist {
+            Some(p) => Ok(Some(Playlist {
+                id: p.id,
+                title: p.title,
+                rules: p.rules.map(|r| serde_json::from_str(&r)).transpose()?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn rename_playlist(&self, id: i64, title: &str) -> Result<()> {
+        let changes = sqlx::query!("UPDATE playlist SET title = ? WHERE id = ?", title, id)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        if changes == 0 {
+            return Err(Error::NotFound("playlist", id.to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn delete_playlist(&self, id: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query!("DELETE FROM playlist_track WHERE playlist_id = ?", id)
+            .execute(&mut tx)
+            .await?;
+        let changes = sqlx::query!("DELETE FROM playlist WHERE id = ?", id)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if changes == 0 {
+            return Err(Error::NotFound("playlist", id.to_string()));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Removes an entry from its playlist, returning how many entries after
+    /// it were shifted up.
+    pub async fn remove_track_from_playlist(&self, entry_id: i64) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let entry = sqlx::query!(
+            r#"DELETE FROM playlist_track WHERE id = ? RETURNING playlist_id as "playlist_id!", position as "position!""#,
+            entry_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| Error::NotFound("playlist entry", entry_id.to_string()))?;
+
+        let changes = sqlx::query!(
+            "UPDATE playlist_track SET position = position - 1 WHERE playlist_id = ? AND position > ?",
+            entry.playlist_id,
+            entry.position,
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
 
         Ok(changes)
     }
 
+    /// Inserts a track into a playlist at `position`, or at the end if
+    /// `position` is past it, returning the new entry's id.
     pub async fn add_track_to_playlist(
         &self,
         track_id: &str,
-        playlist: &str,
+        playlist_id: i64,
         position: i64,
-    ) -> Result<u64> {
-        let changes = sqlx::query!(
-            "UPDATE playlist_track SET position = position + 1 WHERE playlist = ? AND position >= ?",
-            playlist,
+    ) -> Result<i64> {
+        let mut tx = self.db.begin().await?;
+        let len = playlist_len(&mut tx, playlist_id).await?;
+        let position = position.clamp(0, len);
+        sqlx::query!(
+            "UPDATE playlist_track SET position = position + 1 WHERE playlist_id = ? AND position >= ?",
+            playlist_id,
             position,
         )
-        .execute(&self.db)
-        .await?
-        .rows_affected();
+        .execute(&mut tx)
+        .await?;
 
-        sqlx::query!(
-            "INSERT INTO playlist_track VALUES (?, ?, ?)",
-            playlist,
+        let id = sqlx::query!(
+            "INSERT INTO playlist_track (playlist_id, track_id, position) VALUES (?, ?, ?)",
+            playlist_id,
             track_id,
             position,
         )
-        .execute(&self.db)
+        .execute(&mut tx)
+        .await?
+        .last_insert_rowid();
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    /// Moves an entry within its playlist to `position`, shifting the entries
+    /// in between along by one.
+    pub async fn move_playlist_track(&self, entry_id: i64, position: i64) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let entry = sqlx::query!(
+            "SELECT playlist_id, position FROM playlist_track WHERE id = ?",
+            entry_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| Error::NotFound("playlist entry", entry_id.to_string()))?;
+        let (playlist_id, from) = (entry.playlist_id, entry.position);
+        let len = playlist_len(&mut tx, playlist_id).await?;
+        let to = position.clamp(0, len - 1);
+
+        let changes = if to > from {
+            sqlx::query!(
+                "UPDATE playlist_track SET position = position - 1 WHERE playlist_id = ? AND position > ? AND position <= ?",
+                playlist_id,
+                from,
+                to,
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected()
+        } else {
+            sqlx::query!(
+                "UPDATE playlist_track SET position = position + 1 WHERE playlist_id = ? AND position >= ? AND position < ?",
+                playlist_id,
+                to,
+                from,
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected()
+        };
+        sqlx::query!(
+            "UPDATE playlist_track SET position = ? WHERE id = ?",
+            to,
+            entry_id,
+        )
+        .execute(&mut tx)
         .await?;
+        tx.commit().await?;
 
         Ok(changes)
     }
 
-    pub async fn delete_tracks(&self, ids: &Vec<String>) -> Result<u64> {
-        for id in ids {
-            let playlists = sqlx::query!(
-                "SELECT * FROM playlist_track WHERE playlist_track.track_id = ?",
-                id
-            )
+    pub async fn get_playlist_entries(&self, playlist_id: i64) -> Result<Vec<PlaylistEntry>> {
+        let query_str = format!(
+            "SELECT playlist_track.id AS entry_id, playlist_track.position, {}
+            FROM playlist_track
+            JOIN track ON track.id = playlist_track.track_id
+            JOIN album ON album.id = track.album_id
+            WHERE playlist_track.playlist_id = ?
+            ORDER BY playlist_track.position",
+            TRACK_COLUMNS
+        );
+        let rows = sqlx::query(&query_str)
+            .bind(playlist_id)
             .fetch_all(&self.db)
             .await?;
-            for p in playlists {
-                self.remove_track_from_playlist(id, &p.playlist).await?;
+        let res = rows
+            .iter()
+            .map(|row| {
+                Ok(PlaylistEntry {
+                    id: row.try_get("entry_id")?,
+                    position: row.try_get::<i64, _>("position")? as u32,
+                    track: track_from_row(row)?,
+                })
+            })
+            .collect::<sqlx::Result<_>>()?;
+        Ok(res)
+    }
+
+    /// Removes tracks along with everything that refers to them, closing
+    /// up the playlists they were on, all in one transaction.
+    pub async fn delete_tracks(&self, ids: &[String]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut tx = self.db.begin().await?;
+        let mut playlist_entries = 0;
+        let mut res = 0;
+        for chunk in ids.chunks(MAX_IDS_PER_STATEMENT) {
+            let params = placeholders(chunk.len());
+            for table in [
+                "playlist_track",
+                "play_history",
+                "track_genre",
+                "artist_credit",
+                // Copies of a removed track can be added in its place
+                "duplicate_file",
+            ] {
+                let query_str = format!("DELETE FROM {} WHERE track_id IN ( {} )", table, params);
+                let removed = bind_all(sqlx::query(&query_str), chunk)
+                    .execute(&mut tx)
+                    .await?
+                    .rows_affected();
+                if table == "playlist_track" {
+                    playlist_entries += removed;
+                }
             }
+
+            let query_str = format!("DELETE FROM track WHERE id IN ( {} )", params);
+            res += bind_all(sqlx::query(&query_str), chunk)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
         }
-        let params = format!("?{}", ", ?".repeat(ids.len() - 1));
-        let query_str = format!("DELETE FROM track WHERE id IN ( {} )", params);
-        let mut query = sqlx::query(&query_str);
-        for i in ids {
-            query = query.bind(i);
+        if playlist_entries > 0 {
+            renumber_playlists(&mut tx).await?;
         }
-        let res = query.execute(&self.db).await?.rows_affected();
+        tx.commit().await?;
         Ok(res)
     }
 
-    pub async fn clean(&self) -> Result<(u64, u64, u64, u64)> {
-        panic!("don't use this");
-        let res = sqlx::query!("DELETE FROM artist WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.artist = artist.name)")
-            .execute(&self.db)
+    /// Removes artists, albums, genres and playlist entries that no track
+    /// refers to any more.
+    pub async fn clean(&self) -> Result<CleanupSummary> {
+        let mut tx = self.db.begin().await?;
+        let playlist_entries = sqlx::query!("DELETE FROM playlist_track WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.id = playlist_track.track_id)")
+            .execute(&mut tx)
             .await?
             .rows_affected();
-        let res2 = sqlx::query!("DELETE FROM album WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.album_id = album.id)")
-            .execute(&self.db)
+        if playlist_entries > 0 {
+            renumber_playlists(&mut tx).await?;
+        }
+        let albums = sqlx::query!("DELETE FROM album WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.album_id = album.id)")
+            .execute(&mut tx)
             .await?
             .rows_affected();
-        let res3 = sqlx::query!("DELETE FROM genre WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.genre = genre.name)")
-            .execute(&self.db)
+        // Albums refer to artists too, so this has to wait until they're gone
+        let artists = sqlx::query!("DELETE FROM artist WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.artist = artist.name) AND NOT EXISTS (SELECT 1 FROM album WHERE album.artist = artist.name) AND NOT EXISTS (SELECT 1 FROM artist_credit WHERE artist_credit.artist = artist.name)")
+            .execute(&mut tx)
             .await?
             .rows_affected();
-        let res4 = sqlx::query!("DELETE FROM playlist_track WHERE NOT EXISTS (SELECT 1 FROM track WHERE track.id = playlist_track.track_id)")
-            .execute(&self.db)
+        let genres = sqlx::query!("DELETE FROM genre WHERE NOT EXISTS (SELECT 1 FROM track_genre WHERE track_genre.genre = genre.name)")
+            .execute(&mut tx)
             .await?
             .rows_affected();
-        Ok((res, res2, res3, res4))
+        tx.commit().await?;
+
+        Ok(CleanupSummary {
+            artists,
+            albums,
+            genres,
+            playlist_entries,
+            artwork_files: 0,
+        })
     }
 
-    pub async fn get_track_ids(&self) -> Result<HashSet<String>> {
-        let res = sqlx::query!("SELECT id FROM track")
-            .fetch_all(&self.db)
+    /// Returns every artwork file that a track or album points to.
+    pub async fn get_artwork_paths(&self) -> Result<HashSet<PathBuf>> {
+        let res = sqlx::query!(
+            r#"SELECT artwork_path as "artwork_path!" FROM track WHERE artwork_path IS NOT NULL
+            UNION SELECT artwork_path FROM album WHERE artwork_path IS NOT NULL"#
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|row| row.artwork_path.into())
+        .collect();
+        Ok(res)
+    }
+
+    pub async fn get_track_files(&self) -> Result<HashMap<PathBuf, TrackFile>> {
+        let res =
+            sqlx::query!("SELECT id, path, album_id, file_size, modified, content_hash FROM track")
+                .fetch_all(&self.db)
+                .await?
+                .into_iter()
+                .map(|r| {
+                    let path = PathBuf::from(r.path);
+                    let file = TrackFile {
+                        id: r.id,
+                        path: path.clone(),
+                        album_id: r.album_id,
+                        info: FileInfo {
+                            size: r.file_size,
+                            modified: r.modified,
+                        },
+                        content_hash: r.content_hash,
+                    };
+                    (path, file)
+                })
+                .collect();
+        Ok(res)
+    }
+
+    /// Makes the next scan read the tracks' files again, however unchanged
+    /// they look.
+    pub async fn forget_file_info(&self, track_ids: &[String]) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for id in track_ids {
+            sqlx::query!("UPDATE track SET modified = 0 WHERE id = ?", id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_track_file(&self, id: &str) -> Result<Option<TrackFile>> {
+        let res = sqlx::query!(
+            "SELECT id, path, album_id, file_size, modified, content_hash FROM track WHERE id = ?",
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .map(|r| TrackFile {
+            id: r.id,
+            path: r.path.into(),
+            album_id: r.album_id,
+            info: FileInfo {
+                size: r.file_size,
+                modified: r.modified,
+            },
+            content_hash: r.content_hash,
+        });
+        Ok(res)
+    }
+
+    pub async fn get_track(&self, id: &str) -> Result<Option<Track>> {
+        let query_str = format!(
+            "SELECT {} FROM track JOIN album ON album.id = track.album_id WHERE track.id = ?",
+            TRACK_COLUMNS
+        );
+        let row = sqlx::query(&query_str)
+            .bind(id)
+            .fetch_optional(&self.db)
             .await?;
-        Ok(res.into_iter().map(|r| r.id).collect())
+        Ok(row.as_ref().map(track_from_row).transpose()?)
     }
 
     pub async fn get_album(&self, id: &str) -> Result<Option<Album>> {
-        let album = sqlx::query!("SELECT * FROM album WHERE id = ?", id)
-            .fetch_optional(&self.db)
+        fetch_album(&self.db, id).await
+    }
+
+    /// Returns a page of the albums in the library, or only those an artist
+    /// is on or with tracks in a genre if given one, sorted by `sort` or else
+    /// by artist and year.
+    pub async fn get_albums(
+        &self,
+        artist: Option<&str>,
+        genre: Option<&str>,
+        sort: Option<&SmartSort>,
+        offset: u32,
+        limit: Option<u32>,
+    ) -> Result<Page<Album>> {
+        let columns: &[&str] = match sort.map(|s| s.field) {
+            None => &["a.sort_artist", "a.year", "a.sort_title"],
+            Some(SortField::Title | SortField::Album) => &["a.sort_title"],
+            Some(SortField::Artist) => &["a.sort_artist", "a.year"],
+            Some(SortField::Year) => &["COALESCE(year, first_year)"],
+            Some(SortField::Duration) => &["total_duration"],
+            Some(SortField::AddedAt) => &["added_at"],
+            Some(SortField::PlayCount) => &["play_count"],
+            Some(SortField::LastPlayed) => &["last_played"],
+            Some(SortField::Random) => &["RANDOM()"],
+            Some(field) => {
+                return Err(Error::Invalid(format!(
+                    "Albums can't be sorted by {:?}",
+                    field
+                )))
+            }
+        };
+        let order = if sort.map_or(false, |s| s.descending) {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        // Every column is sorted the same way, so artists descend along with
+        // their years rather than only the years
+        let order_by = columns
+            .iter()
+            .map(|column| format!("{} {}", column, order))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut conditions = vec!["1"];
+        let mut params = vec![];
+        if let Some(artist) = artist {
+            // Albums the artist is credited on count too, not just their own
+            conditions.push("(a.artist = ? OR EXISTS (SELECT 1 FROM track JOIN artist_credit ON artist_credit.track_id = track.id WHERE track.album_id = a.id AND artist_credit.artist = ?))");
+            params.push(artist);
+            params.push(artist);
+        }
+        if let Some(genre) = genre {
+            conditions.push("EXISTS (SELECT 1 FROM track JOIN track_genre ON track_genre.track_id = track.id WHERE track.album_id = a.id AND track_genre.genre = ?)");
+            params.push(genre);
+        }
+        let condition = conditions.join(" AND ");
+
+        // Counted from the table, as the view has to add up every album's
+        // tracks
+        let count_str = format!("SELECT COUNT(*) FROM album AS a WHERE {}", condition);
+        let mut count_query = sqlx::query_scalar(&count_str);
+        for param in &params {
+            count_query = count_query.bind(param);
+        }
+        let total: i64 = count_query.fetch_one(&self.db).await?;
+
+        // The page is found first so that only its albums' tracks are added
+        // up, which can be done from the table unless sorting by those sums
+        let source = match sort.map(|s| s.field) {
+            Some(
+                SortField::Year
+                | SortField::Duration
+                | SortField::AddedAt
+                | SortField::PlayCount
+                | SortField::LastPlayed,
+            ) => "album_summary",
+            _ => "album",
+        };
+        let query_str = format!(
+            "SELECT a.id FROM {} AS a WHERE {} ORDER BY {}, a.id LIMIT ? OFFSET ?",
+            source, condition, order_by
+        );
+        let mut query = sqlx::query_scalar(&query_str);
+        for param in &params {
+            query = query.bind(param);
+        }
+        let ids: Vec<String> = query
+            .bind(limit.map_or(-1, i64::from))
+            .bind(offset)
+            .fetch_all(&self.db)
             .await?;
+        let items = self.get_albums_by_id(&ids).await?;
+        Ok(Page {
+            items,
+            total: total as u32,
+        })
+    }
 
-        Ok(album.map(|a| Album {
-            id: a.id,
-            title: a.title,
-            artist: a.artist,
-            track_count: a.track_count as u32,
-            artwork_path: a.artwork_path.map(|a| a.into()),
-        }))
+    /// Reads the albums with `ids`, in the same order. The ids are listed in
+    /// the query rather than selected by a subquery, so that only those
+    /// albums are summed up in `album_summary`.
+    async fn get_albums_by_id(&self, ids: &[String]) -> Result<Vec<Album>> {
+        let mut albums = HashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_IDS_PER_STATEMENT) {
+            let query_str = format!(
+                "SELECT * FROM album_summary WHERE id IN ({})",
+                placeholders(chunk.len())
+            );
+            let mut query = sqlx::query_as::<_, AlbumRow>(&query_str);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            for album in query.fetch_all(&self.db).await? {
+                albums.insert(album.id.clone(), Album::from(album));
+            }
+        }
+        Ok(ids.iter().filter_map(|id| albums.remove(id)).collect())
     }
 
-    pub async fn get_albums_from_artist(&self, artist: String) -> Result<Vec<Album>> {
-        let res = sqlx::query!("SELECT * FROM album WHERE artist = ?", artist)
+    pub async fn get_album_track_ids(&self, album_id: &str) -> Result<Vec<String>> {
+        let res = sqlx::query!("SELECT id FROM track WHERE album_id = ?", album_id)
             .fetch_all(&self.db)
             .await?
             .into_iter()
-            .map(|album| Album {
-                id: album.id,
-                title: album.title,
-                artist: album.artist,
-                track_count: album.track_count as u32,
-                artwork_path: album.artwork_path.map(|p| p.into()),
-            })
+            .map(|track| track.id)
             .collect();
         Ok(res)
     }
 
-    pub async fn get_albums(&self) -> Result<Vec<Album>> {
-        let res = sqlx::query!("SELECT * FROM album")
+    /// Points each album's artwork at the cover art from its folder if it
+    /// has any, as scans do, or else at one of its tracks' artwork, for after
+    /// the artwork embedded in the tracks has changed.
+    pub async fn refresh_album_artwork(
+        &self,
+        folder_artwork: &HashMap<String, Option<PathBuf>>,
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for (id, artwork) in folder_artwork {
+            let artwork = artwork.as_ref().map(|p| String::from(p.to_string_lossy()));
+            sqlx::query!(
+                "UPDATE album SET artwork_path = COALESCE(?, (
+                    SELECT artwork_path FROM track
+                    WHERE track.album_id = album.id AND artwork_path IS NOT NULL
+                    ORDER BY cd_number, track_number
+                    LIMIT 1
+                )) WHERE id = ?",
+                artwork,
+                id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Saves a batch of scanned tracks, along with their albums, artists and
+    /// genres, in a single transaction.
+    pub async fn save_tracks(&self, tracks: &[ScannedTrack]) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for scanned in tracks {
+            let track = &scanned.track;
+            insert_genres(&mut tx, track).await?;
+            insert_artists(&mut tx, track).await?;
+            // A file that was a copy of another track no longer is once it's
+            // a track itself
+            let path = track.path.to_string_lossy();
+            sqlx::query!("DELETE FROM duplicate_file WHERE path = ?", path)
+                .execute(&mut tx)
+                .await?;
+            // Also picks up cover art added to the album's folder since
+            update_album(&mut tx, scanned).await?;
+            if scanned.prev.is_some() {
+                update_track(&mut tx, scanned).await?;
+            } else {
+                insert_track(&mut tx, scanned).await?;
+            }
+            set_track_genres(&mut tx, track).await?;
+            set_track_credits(&mut tx, track).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns a page of the artists in the library, sorted by name.
+    pub async fn get_artists(
+        &self,
+        descending: bool,
+        offset: u32,
+        limit: Option<u32>,
+    ) -> Result<Page<Artist>> {
+        let total = sqlx::query_scalar!("SELECT COUNT(*) FROM artist")
+            .fetch_one(&self.db)
+            .await?;
+        let query_str = format!(
+            "SELECT name FROM artist ORDER BY sort_name {0}, name {0} LIMIT ? OFFSET ?",
+            if descending { "DESC" } else { "ASC" }
+        );
+        let items = sqlx::query(&query_str)
+            .bind(limit.map_or(-1, i64::from))
+            .bind(offset)
             .fetch_all(&self.db)
             .await?
-            .into_iter()
-            .map(|album| Album {
-                id: album.id,
-                title: album.title,
-                artist: album.artist,
-                track_count: album.track_count as u32,
-                artwork_path: album.artwork_path.map(|p| p.into()),
+            .iter()
+            .map(|row| {
+                Ok(Artist {
+                    name: row.try_get("name")?,
+                })
             })
-            .collect();
+            .collect::<sqlx::Result<_>>()?;
+        Ok(Page {
+            items,
+            total: total as u32,
+        })
+    }
+
+    /// Returns every genre with how many tracks and albums are in it.
+    pub async fn get_genres(&self) -> Result<Vec<Genre>> {
+        let rows = sqlx::query(
+            "SELECT genre.name, COUNT(track.id) AS track_count, COUNT(DISTINCT track.album_id) AS album_count
+            FROM genre
+            LEFT JOIN track_genre ON track_genre.genre = genre.name
+            LEFT JOIN track ON track.id = track_genre.track_id
+            GROUP BY genre.name
+            ORDER BY genre.name COLLATE natural",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let res = rows
+            .iter()
+            .map(|row| {
+                Ok(Genre {
+                    name: row.try_get("name")?,
+                    track_count: row.try_get::<i64, _>("track_count")? as u32,
+                    album_count: row.try_get::<i64, _>("album_count")? as u32,
+                })
+            })
+            .collect::<sqlx::Result<_>>()?;
         Ok(res)
     }
 
-    pub async fn get_tracks_from_album(&self, album_id: String) -> Result<Vec<Track>> {
-        let album = match self.get_album(&album_id).await? {
-            Some(a) => a,
-            None => return Ok(Vec::new()),
+    /// Searches the full-text indexes, ranking titles above artists.
+    pub async fn search(&self, query: &str, limit: u32) -> Result<SearchResults> {
+        let query = match match_query(query) {
+            Some(q) => q,
+            None => {
+                return Ok(SearchResults {
+                    tracks: vec![],
+                    albums: vec![],
+                    artists: vec![],
+                })
+            }
         };
 
-        let res = sqlx::query!("SELECT * FROM track WHERE album_id = ?", album_id)
+        let query_str = format!(
+            "SELECT {} FROM track_search JOIN track_search_key ON track_search_key.key = track_search.rowid JOIN track ON track.id = track_search_key.id JOIN album ON album.id = track.album_id WHERE track_search MATCH ? ORDER BY bm25(track_search, 4.0, 1.0, 1.0) LIMIT ?",
+            TRACK_COLUMNS
+        );
+        let rows = sqlx::query(&query_str)
+            .bind(&query)
+            .bind(limit)
+            .fetch_all(&self.db)
+            .await?;
+        let tracks = rows
+            .iter()
+            .map(track_from_row)
+            .collect::<sqlx::Result<_>>()?;
+
+        let albums = sqlx::query_as!(
+            AlbumRow,
+            r#"SELECT album_summary.id as "id!", album_summary.title as "title!", album_summary.artist as "artist!", album_summary.artwork_path, album_summary.year, album_summary.compilation as "compilation!: bool", track_count as "track_count!", total_duration as "total_duration!", disc_count as "disc_count!", first_year as "first_year: i64", last_year as "last_year: i64" FROM album_search JOIN album_search_key ON album_search_key.key = album_search.rowid JOIN album_summary ON album_summary.id = album_search_key.id WHERE album_search MATCH ? ORDER BY bm25(album_search, 4.0, 1.0) LIMIT ?"#,
+            query,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(Album::from)
+        .collect();
+
+        let artists = sqlx::query_as!(
+            Artist,
+            "SELECT artist.name FROM artist_search JOIN artist_search_key ON artist_search_key.key = artist_search.rowid JOIN artist ON artist.name = artist_search_key.name WHERE artist_search MATCH ? ORDER BY rank LIMIT ?",
+            query,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(SearchResults {
+            tracks,
+            albums,
+            artists,
+        })
+    }
+
+    pub async fn get_scan_errors(&self) -> Result<Vec<ScanError>> {
+        let res = sqlx::query!("SELECT * FROM scan_error ORDER BY path")
             .fetch_all(&self.db)
             .await?
             .into_iter()
-            .map(move |track| Track {
-                id: track.id,
-                path: track.path.into(),
-                duration: track.duration as u32,
-                metadata: Metadata {
-                    title: track.title,
-                    artist: track.artist,
-                    song_artist: track.song_artist,
-                    album: album.title.clone(),
-                    track_number: track.track_number.map(|n| n as u32),
-                    cd_number: track.cd_number.map(|n| n as u32),
-                    year: track.year.map(|n| n as u32),
-                    genre: track.genre,
-                    artwork_path: track.artwork_path.map(|p| p.into()),
-                },
+            .map(|e| ScanError {
+                path: e.path.into(),
+                kind: e.kind,
+                message: e.message,
+                occurred_at: e.occurred_at,
             })
             .collect();
         Ok(res)
     }
 
-    pub async fn get_tracks(&self) -> Result<Vec<Track>> {
-        let res = sqlx::query!("SELECT * FROM track")
+    /// Replaces the errors recorded for files under `cleared_roots` with
+    /// `errors`, keeping any older errors for files elsewhere.
+    pub async fn record_scan_errors(
+        &self,
+        cleared_roots: &[PathBuf],
+        errors: &[ScanError],
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        if !cleared_roots.is_empty() {
+            let paths = sqlx::query!("SELECT path FROM scan_error")
+                .fetch_all(&mut tx)
+                .await?;
+            for row in paths {
+                if cleared_roots
+                    .iter()
+                    .any(|r| Path::new(&row.path).starts_with(r))
+                {
+                    sqlx::query!("DELETE FROM scan_error WHERE path = ?", row.path)
+                        .execute(&mut tx)
+                        .await?;
+                }
+            }
+        }
+        for error in errors {
+            let path = error.path.to_string_lossy();
+            sqlx::query!(
+                "REPLACE INTO scan_error VALUES (?, ?, ?, ?)",
+                path,
+                error.kind,
+                error.message,
+                error.occurred_at,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the files left out of the library as copies of other tracks,
+    /// along with how they looked when they were read.
+    pub async fn get_duplicate_files(&self) -> Result<Vec<(DuplicateFile, FileInfo)>> {
+        let res = sqlx::query!("SELECT path, track_id, file_size, modified FROM duplicate_file")
             .fetch_all(&self.db)
             .await?
             .into_iter()
-            .map(|track| Track {
-                id: track.id,
-                path: track.path.into(),
-                duration: track.duration as u32,
-                metadata: Metadata {
-                    title: track.title,
-                    artist: track.artist,
-                    song_artist: track.song_artist,
-                    album: track.album_id,
-                    track_number: track.track_number.map(|n| n as u32),
-                    cd_number: track.cd_number.map(|n| n as u32),
-                    year: track.year.map(|n| n as u32),
-                    genre: track.genre,
-                    artwork_path: track.artwork_path.map(|p| p.into()),
-                },
+            .map(|r| {
+                let file = DuplicateFile {
+                    path: r.path.into(),
+                    track_id: r.track_id,
+                };
+                let info = FileInfo {
+                    size: r.file_size,
+                    modified: r.modified,
+                };
+                (file, info)
             })
             .collect();
         Ok(res)
     }
 
-    pub async fn update_album(&self, track: &Track) -> Result<u64> {
-        let unique = format!("{} - {}", track.metadata.artist, track.metadata.album);
-        let album_id = format!("{:x}", md5::compute(unique));
-        let existing_album = self.get_album(&album_id).await?;
-
-        let album = if let Some(mut a) = existing_album {
-            a.track_count += 1;
-            if a.artwork_path.is_none() {
-                a.artwork_path = get_artwork(track);
-            }
-            a
-        } else {
-            Album {
-                id: album_id,
-                title: track.metadata.album.clone(),
-                artist: track.metadata.artist.clone(),
-                track_count: 1,
-                artwork_path: get_artwork(track),
-            }
-        };
+    /// Records the copies found by a scan and forgets the ones that have
+    /// gone. Copies of tracks that have since been removed aren't recorded,
+    /// so they're read again next time.
+    pub async fn update_duplicate_files(
+        &self,
+        found: &[(DuplicateFile, FileInfo)],
+        gone: &[PathBuf],
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for path in gone {
+            let path = path.to_string_lossy();
+            sqlx::query!("DELETE FROM duplicate_file WHERE path = ?", path)
+                .execute(&mut tx)
+                .await?;
+        }
+        for (file, info) in found {
+            let path = file.path.to_string_lossy();
+            sqlx::query!(
+                "REPLACE INTO duplicate_file (path, track_id, file_size, modified)
+                SELECT ?, id, ?, ? FROM track WHERE id = ?",
+                path,
+                info.size,
+                info.modified,
+                file.track_id,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
 
-        let artwork = album
-            .artwork_path
-            .map(|p| String::from(p.to_string_lossy()));
+    pub async fn get_library_roots(&self) -> Result<Vec<LibraryRoot>> {
+        sqlx::query!("SELECT * FROM library_root")
+            .fetch_all(&self.db)
+            .await?
+            .into_iter()
+            .map(|root| {
+                Ok(LibraryRoot {
+                    path: root.path.into(),
+                    follow_symlinks: root.follow_symlinks,
+                    include_hidden: root.include_hidden,
+                    allowed_extensions: serde_json::from_str(&root.allowed_extensions)?,
+                    denied_extensions: serde_json::from_str(&root.denied_extensions)?,
+                    exclude_globs: serde_json::from_str(&root.exclude_globs)?,
+                })
+            })
+            .collect()
+    }
 
+    pub async fn add_library_root(&self, root: &LibraryRoot) -> Result<u64> {
+        let path = root.path.to_string_lossy();
+        let allowed = serde_json::to_string(&root.allowed_extensions)?;
+        let denied = serde_json::to_string(&root.denied_extensions)?;
+        let excluded = serde_json::to_string(&root.exclude_globs)?;
         let res = sqlx::query!(
-            "REPLACE INTO album VALUES (?, ?, ?, ?, ?)",
-            album.id,
-            album.title,
-            album.artist,
-            album.track_count,
-            artwork,
+            "REPLACE INTO library_root VALUES (?, ?, ?, ?, ?, ?)",
+            path,
+            root.follow_symlinks,
+            root.include_hidden,
+            allowed,
+            denied,
+            excluded,
         )
         .execute(&self.db)
         .await?
         .rows_affected();
-
         Ok(res)
     }
 
-    pub async fn update_artist(&self, track: &Track) -> Result<u64> {
-        let res = sqlx::query!(
-            "INSERT OR IGNORE INTO artist VALUES (?)",
-            track.metadata.artist
-        )
-        .execute(&self.db)
-        .await?
-        .rows_affected();
+    pub async fn remove_library_root(&self, path: &Path) -> Result<u64> {
+        let path = path.to_string_lossy();
+        let res = sqlx::query!("DELETE FROM library_root WHERE path = ?", path)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
         Ok(res)
     }
 
-    pub async fn update_genre(&self, track: &Track) -> Result<u64> {
-        let res = sqlx::query!(
-            "INSERT OR IGNORE INTO genre VALUES (?)",
-            track.metadata.genre
-        )
-        .execute(&self.db)
-        .await?
-        .rows_affected();
+    pub async fn get_setting<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let setting = sqlx::query!("SELECT value FROM setting WHERE key = ?", key)
+            .fetch_optional(&self.db)
+            .await?;
+        match setting {
+            Some(s) => Ok(Some(serde_json::from_str(&s.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_setting<T: Serialize>(&self, key: &str, value: &T) -> Result<u64> {
+        let value = serde_json::to_string(value)?;
+        let res = sqlx::query!("REPLACE INTO setting VALUES (?, ?)", key, value)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
         Ok(res)
     }
 
-    pub async fn add_track(&self, track: Track) -> Result<u64> {
-        let unique = format!("{} - {}", track.metadata.artist, track.metadata.album);
-        let album_id = format!("{:x}", md5::compute(unique));
-        let path = track.path.to_string_lossy();
-        let artwork = track
-            .metadata
-            .artwork_path
-            .map(|p| String::from(p.to_string_lossy()));
-        let res = sqlx::query!(
-            "INSERT INTO track VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-            track.id,
-            path,
-            track.metadata.title,
-            track.duration,
-            track.metadata.artist,
-            album_id,
-            track.metadata.genre,
-            track.metadata.song_artist,
-            track.metadata.track_number,
-            track.metadata.cd_number,
-            track.metadata.year,
-            artwork,
+    pub async fn set_rating(&self, track_id: &str, rating: Option<u8>) -> Result<()> {
+        let changes = sqlx::query!("UPDATE track SET rating = ? WHERE id = ?", rating, track_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        if changes == 0 {
+            return Err(Error::NotFound("track", track_id.into()));
+        }
+        Ok(())
+    }
+
+    pub async fn set_loved(&self, track_id: &str, loved: bool) -> Result<()> {
+        let changes = sqlx::query!("UPDATE track SET loved = ? WHERE id = ?", loved, track_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        if changes == 0 {
+            return Err(Error::NotFound("track", track_id.into()));
+        }
+        Ok(())
+    }
+
+    /// Records that a track was played, or skipped after `listened` seconds.
+    pub async fn record_play(&self, track_id: &str, listened: u32, skipped: bool) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO play_history (track_id, played_at, listened, skipped) VALUES (?, CAST(strftime('%s', 'now') AS INTEGER), ?, ?)",
+            track_id,
+            listened,
+            skipped,
         )
         .execute(&self.db)
-        .await?
-        .rows_affected();
-        Ok(res)
+        .await?;
+        Ok(())
     }
 
-    pub async fn get_artists(&self) -> Result<Vec<Artist>> {
-        let res = sqlx::query_as!(Artist, "SELECT * FROM artist")
+    pub async fn get_playlists(&self) -> Result<Vec<Playlist>> {
+        let mut res = vec![];
+        for p in sqlx::query!("SELECT id, title, rules FROM playlist")
             .fetch_all(&self.db)
-            .await?;
+            .await?
+        {
+            res.push(Playlist {
+                id: p.id,
+                title: p.title,
+                rules: p.rules.map(|r| serde_json::from_str(&r)).transpose()?,
+            });
+        }
         Ok(res)
     }
+}
 
-    pub async fn get_playlists(&self) -> Result<Vec<Playlist>> {
-        let res = sqlx::query_as!(Playlist, "SELECT * FROM playlist")
-            .fetch_all(&self.db)
-            .await?;
-        Ok(res)
+/// Returns how many entries a playlist has, failing if it doesn't exist or
+/// is a smart playlist, whose entries can't be edited.
+async fn playlist_len(conn: &mut SqliteConnection, playlist_id: i64) -> Result<i64> {
+    let playlist = sqlx::query!("SELECT rules FROM playlist WHERE id = ?", playlist_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| Error::NotFound("playlist", playlist_id.to_string()))?;
+    if playlist.rules.is_some() {
+        return Err(Error::SmartPlaylist);
+    }
+    let len = sqlx::query!(
+        r#"SELECT COUNT(*) as "len!: i64" FROM playlist_track WHERE playlist_id = ?"#,
+        playlist_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .len;
+    Ok(len)
+}
+
+/// Works out the sort keys of tracks, albums and artists saved before they
+/// had them, in one transaction.
+async fn fill_sort_keys(pool: &SqlitePool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let tracks = sqlx::query!(
+        "SELECT track.id, track.title, track.artist, album.title AS album_title
+        FROM track JOIN album ON album.id = track.album_id
+        WHERE track.sort_title IS NULL"
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    for track in tracks {
+        let sort_title = natural_key(&track.title);
+        let sort_artist = natural_key(&track.artist);
+        let sort_album = natural_key(&track.album_title);
+        sqlx::query!(
+            "UPDATE track SET sort_title = ?, sort_artist = ?, sort_album = ? WHERE id = ?",
+            sort_title,
+            sort_artist,
+            sort_album,
+            track.id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let albums = sqlx::query!("SELECT id, title, artist FROM album WHERE sort_title IS NULL")
+        .fetch_all(&mut tx)
+        .await?;
+    for album in albums {
+        let sort_title = natural_key(&album.title);
+        let sort_artist = natural_key(&album.artist);
+        sqlx::query!(
+            "UPDATE album SET sort_title = ?, sort_artist = ? WHERE id = ?",
+            sort_title,
+            sort_artist,
+            album.id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let artists = sqlx::query!("SELECT name FROM artist WHERE sort_name IS NULL")
+        .fetch_all(&mut tx)
+        .await?;
+    for artist in artists {
+        let sort_name = natural_key(&artist.name);
+        sqlx::query!(
+            "UPDATE artist SET sort_name = ? WHERE name = ?",
+            sort_name,
+            artist.name
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns `count` comma-separated parameters, for an `IN` list.
+fn placeholders(count: usize) -> String {
+    format!("?{}", ", ?".repeat(count.saturating_sub(1)))
+}
+
+fn bind_all<'q>(
+    mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    values: &'q [String],
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    for value in values {
+        query = query.bind(value);
+    }
+    query
+}
+
+/// Numbers every playlist's entries from 0 again, closing the gaps left by
+/// entries that were removed without shifting the rest. Entries that somehow
+/// share a position are kept in the order they were added.
+async fn renumber_playlists(conn: &mut SqliteConnection) -> Result<u64> {
+    let res = sqlx::query!(
+        "WITH numbered AS (
+            SELECT id, ROW_NUMBER() OVER (PARTITION BY playlist_id ORDER BY position, id) - 1 AS position
+            FROM playlist_track
+        )
+        UPDATE playlist_track SET position = (
+            SELECT numbered.position FROM numbered WHERE numbered.id = playlist_track.id
+        )"
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    Ok(res)
+}
+
+/// Turns what was typed into an FTS5 query matching every word as a prefix,
+/// or `None` if there are no words in it. Words are quoted so nothing typed
+/// is read as query syntax.
+fn match_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Reads a track from a row of `TRACK_COLUMNS`.
+fn track_from_row(row: &SqliteRow) -> sqlx::Result<Track> {
+    let path: String = row.try_get("path")?;
+    let artwork_path: Option<String> = row.try_get("artwork_path")?;
+    let genres: Option<String> = row.try_get("genres")?;
+    let credits: Option<String> = row.try_get("credits")?;
+    Ok(Track {
+        id: row.try_get("id")?,
+        path: path.into(),
+        duration: row.try_get::<i64, _>("duration")? as u32,
+        metadata: Metadata {
+            title: row.try_get("title")?,
+            artist: row.try_get("artist")?,
+            song_artist: row.try_get("song_artist")?,
+            album: row.try_get("album_title")?,
+            track_number: row
+                .try_get::<Option<i64>, _>("track_number")?
+                .map(|n| n as u32),
+            cd_number: row
+                .try_get::<Option<i64>, _>("cd_number")?
+                .map(|n| n as u32),
+            year: row.try_get::<Option<i64>, _>("year")?.map(|n| n as u32),
+            genre: row.try_get("genre")?,
+            genres: genres
+                .map(|g| g.split(';').map(String::from).collect())
+                .unwrap_or_default(),
+            credits: credits.as_deref().map(parse_credits).unwrap_or_default(),
+            artwork_path: artwork_path.map(|p| p.into()),
+            album_artist: row.try_get("album_artist")?,
+            compilation: row.try_get("compilation")?,
+            musicbrainz_album_id: row.try_get("musicbrainz_album_id")?,
+            rating: row.try_get::<Option<i64>, _>("rating")?.map(|n| n as u8),
+            loved: row.try_get("loved")?,
+        },
+        stats: TrackStats {
+            play_count: row.try_get::<i64, _>("play_count")? as u32,
+            skip_count: row.try_get::<i64, _>("skip_count")? as u32,
+            last_played: row.try_get("last_played")?,
+        },
+    })
+}
+
+/// Reads the credits selected in `TRACK_COLUMNS`, written as `role:artist`
+/// and separated by unit separators.
+fn parse_credits(credits: &str) -> Vec<ArtistCredit> {
+    credits
+        .split('\u{1f}')
+        .filter_map(|credit| {
+            let (role, name) = credit.split_once(':')?;
+            Some(ArtistCredit {
+                name: name.into(),
+                role: ArtistRole::parse(role)?,
+            })
+        })
+        .collect()
+}
+
+/// A row of the `album_summary` view.
+#[derive(sqlx::FromRow)]
+struct AlbumRow {
+    id: String,
+    title: String,
+    artist: String,
+    artwork_path: Option<String>,
+    year: Option<i64>,
+    compilation: bool,
+    track_count: i64,
+    total_duration: i64,
+    disc_count: i64,
+    first_year: Option<i64>,
+    last_year: Option<i64>,
+}
+
+impl From<AlbumRow> for Album {
+    fn from(album: AlbumRow) -> Self {
+        Self {
+            id: album.id,
+            title: album.title,
+            artist: album.artist,
+            artwork_path: album.artwork_path.map(|p| p.into()),
+            year: album.year.map(|n| n as u32),
+            compilation: album.compilation,
+            track_count: album.track_count as u32,
+            total_duration: album.total_duration as u32,
+            disc_count: album.disc_count as u32,
+            first_year: album.first_year.map(|n| n as u32),
+            last_year: album.last_year.map(|n| n as u32),
+        }
     }
 }
 
-fn get_artwork(track: &Track) -> Option<PathBuf> {
-    let mut path = track.path.clone();
-    path.pop();
+async fn fetch_album<'e, E>(executor: E, id: &str) -> Result<Option<Album>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let album = sqlx::query_as!(AlbumRow, r#"SELECT id as "id!", title as "title!", artist as "artist!", artwork_path, year, compilation as "compilation!: bool", track_count as "track_count!", total_duration as "total_duration!", disc_count as "disc_count!", first_year as "first_year: i64", last_year as "last_year: i64" FROM album_summary WHERE id = ?"#, id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(album.map(Album::from))
+}
+
+async fn update_album(conn: &mut SqliteConnection, scanned: &ScannedTrack) -> Result<u64> {
+    let track = &scanned.track;
+    let album_id = album_id(track);
+    let existing_album = fetch_album(&mut *conn, &album_id).await?;
 
-    const ARTWORK_FILENAMES: [&str; 6] = [
-        "cover.png",
-        "artwork.png",
-        "folder.png",
-        "cover.jpg",
-        "artwork.jpg",
-        "folder.jpg",
-    ];
-    for filename in ARTWORK_FILENAMES {
-        let file = path.join(filename);
-        if file.is_file() {
-            return Some(file);
+    let album = if let Some(mut a) = existing_album {
+        if a.artwork_path.is_none() {
+            a.artwork_path = album_artwork(scanned);
+        }
+        // Tracks without an album artist are grouped by folder, so if they
+        // turn out to have different artists it's a compilation
+        if track.metadata.album_artist.is_none() && a.artist != track.metadata.artist {
+            a.artist = VARIOUS_ARTISTS.to_string();
+            a.compilation = true;
+        }
+        a
+    } else {
+        Album {
+            id: album_id,
+            title: track.metadata.album.clone(),
+            artist: track.metadata.artist.clone(),
+            artwork_path: album_artwork(scanned),
+            year: track.metadata.year,
+            compilation: track.metadata.compilation,
+            // Worked out from the track table when read back
+            track_count: 0,
+            total_duration: 0,
+            disc_count: 0,
+            first_year: None,
+            last_year: None,
         }
+    };
+
+    if album.artist == VARIOUS_ARTISTS {
+        insert_artist(&mut *conn, VARIOUS_ARTISTS).await?;
+    }
+
+    let artwork = album
+        .artwork_path
+        .map(|p| String::from(p.to_string_lossy()));
+    let directory = track
+        .path
+        .parent()
+        .map(|p| String::from(p.to_string_lossy()));
+
+    let sort_title = natural_key(&album.title);
+    let sort_artist = natural_key(&album.artist);
+
+    let res = sqlx::query!(
+        // Updated in place rather than replaced, so the album keeps its row in
+        // the search index rather than being deleted and added again
+        "INSERT INTO album (id, title, artist, artwork_path, year, compilation, musicbrainz_id, directory, sort_title, sort_artist) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET title = excluded.title, artist = excluded.artist, artwork_path = excluded.artwork_path, year = excluded.year, compilation = excluded.compilation, musicbrainz_id = excluded.musicbrainz_id, directory = excluded.directory, sort_title = excluded.sort_title, sort_artist = excluded.sort_artist",
+        album.id,
+        album.title,
+        album.artist,
+        artwork,
+        album.year,
+        album.compilation,
+        track.metadata.musicbrainz_album_id,
+        directory,
+        sort_title,
+        sort_artist,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    Ok(res)
+}
+
+async fn insert_artists(conn: &mut SqliteConnection, track: &Track) -> Result<u64> {
+    let credited = track.metadata.credits.iter().map(|c| &c.name);
+    let mut res = 0;
+    for name in std::iter::once(&track.metadata.artist).chain(credited) {
+        res += insert_artist(&mut *conn, name).await?;
+    }
+    Ok(res)
+}
+
+async fn insert_artist(conn: &mut SqliteConnection, name: &str) -> Result<u64> {
+    let sort_name = natural_key(name);
+    let res = sqlx::query!(
+        "INSERT OR IGNORE INTO artist (name, sort_name) VALUES (?, ?)",
+        name,
+        sort_name
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    Ok(res)
+}
+
+/// Replaces the credits on a track with those in its metadata.
+async fn set_track_credits(conn: &mut SqliteConnection, track: &Track) -> Result<()> {
+    sqlx::query!("DELETE FROM artist_credit WHERE track_id = ?", track.id)
+        .execute(&mut *conn)
+        .await?;
+    for (position, credit) in track.metadata.credits.iter().enumerate() {
+        let position = position as i64;
+        let role = credit.role.as_str();
+        sqlx::query!(
+            "INSERT INTO artist_credit (track_id, artist, role, position) VALUES (?, ?, ?, ?)",
+            track.id,
+            credit.name,
+            role,
+            position
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn insert_genres(conn: &mut SqliteConnection, track: &Track) -> Result<u64> {
+    let mut res = 0;
+    for genre in &track.metadata.genres {
+        res += sqlx::query!("INSERT OR IGNORE INTO genre VALUES (?)", genre)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    }
+    Ok(res)
+}
+
+/// Replaces the genres linked to a track with those in its metadata.
+async fn set_track_genres(conn: &mut SqliteConnection, track: &Track) -> Result<()> {
+    sqlx::query!("DELETE FROM track_genre WHERE track_id = ?", track.id)
+        .execute(&mut *conn)
+        .await?;
+    for (position, genre) in track.metadata.genres.iter().enumerate() {
+        let position = position as i64;
+        sqlx::query!(
+            "INSERT INTO track_genre (track_id, genre, position) VALUES (?, ?, ?)",
+            track.id,
+            genre,
+            position
+        )
+        .execute(&mut *conn)
+        .await?;
     }
+    Ok(())
+}
 
-    if let Some(1) = track.metadata.track_number {
-        track.metadata.artwork_path.clone()
+async fn insert_track(conn: &mut SqliteConnection, scanned: &ScannedTrack) -> Result<u64> {
+    let track = &scanned.track;
+    let album_id = album_id(track);
+    let path = track.path.to_string_lossy();
+    let artwork = track
+        .metadata
+        .artwork_path
+        .as_ref()
+        .map(|p| String::from(p.to_string_lossy()));
+    let sort_title = natural_key(&track.metadata.title);
+    let sort_artist = natural_key(&track.metadata.artist);
+    let sort_album = natural_key(&track.metadata.album);
+    let res = sqlx::query!(
+        "INSERT INTO track (id, path, title, duration, artist, album_id, genre, song_artist, track_number, cd_number, year, artwork_path, file_size, modified, content_hash, album_artist, compilation, musicbrainz_album_id, rating, sort_title, sort_artist, sort_album, added_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER))",
+        track.id,
+        path,
+        track.metadata.title,
+        track.duration,
+        track.metadata.artist,
+        album_id,
+        track.metadata.genre,
+        track.metadata.song_artist,
+        track.metadata.track_number,
+        track.metadata.cd_number,
+        track.metadata.year,
+        artwork,
+        scanned.info.size,
+        scanned.info.modified,
+        scanned.content_hash,
+        track.metadata.album_artist,
+        track.metadata.compilation,
+        track.metadata.musicbrainz_album_id,
+        track.metadata.rating,
+        sort_title,
+        sort_artist,
+        sort_album,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    Ok(res)
+}
+
+async fn update_track(conn: &mut SqliteConnection, scanned: &ScannedTrack) -> Result<u64> {
+    let track = &scanned.track;
+    let album_id = album_id(track);
+    let path = track.path.to_string_lossy();
+    let artwork = track
+        .metadata
+        .artwork_path
+        .as_ref()
+        .map(|p| String::from(p.to_string_lossy()));
+    let sort_title = natural_key(&track.metadata.title);
+    let sort_artist = natural_key(&track.metadata.artist);
+    let sort_album = natural_key(&track.metadata.album);
+    let res = sqlx::query!(
+        "UPDATE track SET path = ?, title = ?, duration = ?, artist = ?, album_id = ?, genre = ?, song_artist = ?, track_number = ?, cd_number = ?, year = ?, artwork_path = ?, file_size = ?, modified = ?, content_hash = ?, album_artist = ?, compilation = ?, musicbrainz_album_id = ?, rating = COALESCE(?, rating), sort_title = ?, sort_artist = ?, sort_album = ? WHERE id = ?",
+        path,
+        track.metadata.title,
+        track.duration,
+        track.metadata.artist,
+        album_id,
+        track.metadata.genre,
+        track.metadata.song_artist,
+        track.metadata.track_number,
+        track.metadata.cd_number,
+        track.metadata.year,
+        artwork,
+        scanned.info.size,
+        scanned.info.modified,
+        scanned.content_hash,
+        track.metadata.album_artist,
+        track.metadata.compilation,
+        track.metadata.musicbrainz_album_id,
+        // Ratings are only written to tags when asked, so keep the one in
+        // the library when the file doesn't have one
+        track.metadata.rating,
+        sort_title,
+        sort_artist,
+        sort_album,
+        track.id,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    Ok(res)
+}
+
+/// Albums are identified by their MusicBrainz release id when they have one.
+/// Otherwise they're told apart by album artist, title and year, while tracks
+/// with no album artist are grouped by title within their folder so that
+/// compilations aren't split up by track artist.
+pub fn album_id(track: &Track) -> String {
+    let metadata = &track.metadata;
+    let unique = if let Some(mbid) = &metadata.musicbrainz_album_id {
+        format!("musicbrainz:{}", mbid)
+    } else if let Some(artist) = &metadata.album_artist {
+        let year = metadata.year.map(|y| y.to_string()).unwrap_or_default();
+        format!("{} - {} - {}", artist, metadata.album, year)
     } else {
-        None
+        let dir = track.path.parent().unwrap_or(&track.path);
+        format!("{} - {}", dir.to_string_lossy(), metadata.album)
+    };
+    format!("{:x}", md5::compute(unique))
+}
+
+/// Albums use the cover image in their folder, or else the art embedded in
+/// their first track.
+fn album_artwork(scanned: &ScannedTrack) -> Option<PathBuf> {
+    scanned.folder_artwork.clone().or_else(|| {
+        let metadata = &scanned.track.metadata;
+        match metadata.track_number {
+            Some(1) => metadata.artwork_path.clone(),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_store() -> Store {
+        Store::open("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn renumbering_keeps_playlist_order() {
+        let store = test_store().await;
+        let mut conn = store.db.acquire().await.unwrap();
+        // The entries don't need real tracks to be renumbered
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO playlist (id, title) VALUES (1, 'One'), (2, 'Two')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        // Positions out of step with the ids, with gaps and a tie
+        sqlx::query(
+            "INSERT INTO playlist_track (id, playlist_id, track_id, position) VALUES
+            (1, 1, 'a', 5), (2, 1, 'b', 3), (3, 1, 'c', 0),
+            (4, 2, 'd', 7), (5, 2, 'e', 2), (6, 2, 'f', 7)",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        renumber_playlists(&mut conn).await.unwrap();
+
+        let positions: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT id, position FROM playlist_track ORDER BY id")
+                .fetch_all(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(positions, [(1, 2), (2, 1), (3, 0), (4, 1), (5, 0), (6, 2)]);
+    }
+
+    #[tokio::test]
+    async fn deleting_more_tracks_than_fit_in_a_statement() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Artist');
+            INSERT INTO album (id, title, artist) VALUES ('album', 'Album', 'Artist');
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1200)
+            INSERT INTO track (id, path, title, duration, artist, album_id, file_size, modified)
+            SELECT printf('t%04d', i), printf('/music/%04d.mp3', i), 'Track', 0, 'Artist', 'album', 0, 0 FROM n;
+            INSERT INTO playlist (id, title) VALUES (1, 'Playlist');
+            INSERT INTO playlist_track (playlist_id, track_id, position) VALUES
+                (1, 't0001', 0), (1, 't1200', 1), (1, 't0002', 2), (1, 't1199', 3);
+            INSERT INTO play_history (track_id, played_at, listened) VALUES ('t0003', 0, 0);",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+
+        let ids: Vec<String> = (1..=1100).map(|i| format!("t{:04}", i)).collect();
+        assert_eq!(store.delete_tracks(&ids).await.unwrap(), 1100);
+
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM track")
+            .fetch_one(&store.db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 100);
+        let entries: Vec<(String, i64)> =
+            sqlx::query_as("SELECT track_id, position FROM playlist_track ORDER BY position")
+                .fetch_all(&store.db)
+                .await
+                .unwrap();
+        assert_eq!(entries, [("t1200".into(), 0), ("t1199".into(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_only_kept_for_tracks_in_the_library() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Artist');
+            INSERT INTO album (id, title, artist) VALUES ('album', 'Album', 'Artist');
+            INSERT INTO track (id, path, title, duration, artist, album_id, file_size, modified)
+            VALUES ('original', '/music/a.mp3', 'Track', 0, 'Artist', 'album', 0, 0);",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+
+        let info = FileInfo {
+            size: 100,
+            modified: 200,
+        };
+        let copy = |path: &str, track_id: &str| {
+            let file = DuplicateFile {
+                path: path.into(),
+                track_id: track_id.into(),
+            };
+            (file, info)
+        };
+        store
+            .update_duplicate_files(
+                &[
+                    copy("/music/b.mp3", "original"),
+                    copy("/music/c.mp3", "gone"),
+                ],
+                &[],
+            )
+            .await
+            .unwrap();
+        let duplicates = store.get_duplicate_files().await.unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0.path, Path::new("/music/b.mp3"));
+        assert_eq!(duplicates[0].1, info);
+
+        store.delete_tracks(&["original".into()]).await.unwrap();
+        assert!(store.get_duplicate_files().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pages_are_sorted_by_the_keys_filled_in_on_open() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Artist 10'), ('artist 9'), ('Ártist 1');
+            INSERT INTO album (id, title, artist) VALUES
+                ('a', 'Album 10', 'Artist 10'), ('b', 'album 9', 'artist 9'), ('c', 'Álbum 1', 'Ártist 1');
+            INSERT INTO track (id, path, title, duration, artist, album_id, file_size, modified) VALUES
+                ('t1', '/music/1.mp3', 'Track 2', 60, 'Artist 10', 'a', 0, 0),
+                ('t2', '/music/2.mp3', 'Track 10', 60, 'Artist 10', 'a', 0, 0),
+                ('t3', '/music/3.mp3', 'Track 1', 30, 'Ártist 1', 'c', 0, 0);",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+        fill_sort_keys(&store.db).await.unwrap();
+
+        let albums = store
+            .get_albums(None, None, None, 1, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(albums.total, 3);
+        let ids: Vec<&str> = albums.items.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        assert_eq!(albums.items[1].track_count, 2);
+        assert_eq!(albums.items[1].total_duration, 120);
+
+        let by_title = SmartSort {
+            field: SortField::Title,
+            descending: false,
+        };
+        let tracks = store
+            .get_tracks(None, None, Some(&by_title), 0, None)
+            .await
+            .unwrap();
+        let titles: Vec<&str> = tracks
+            .items
+            .iter()
+            .map(|t| t.metadata.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Track 1", "Track 2", "Track 10"]);
+
+        let artists = store.get_artists(true, 0, None).await.unwrap();
+        let names: Vec<&str> = artists.items.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Artist 10", "artist 9", "Ártist 1"]);
+    }
+
+    #[tokio::test]
+    async fn search_finds_tracks_after_vacuum() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Björk');
+            INSERT INTO album (id, title, artist) VALUES ('album', 'Homogenic', 'Björk');
+            INSERT INTO track (id, path, title, duration, artist, album_id, file_size, modified) VALUES
+                ('t1', '/music/1.mp3', 'Hunter', 0, 'Björk', 'album', 0, 0),
+                ('t2', '/music/2.mp3', 'Jóga', 0, 'Björk', 'album', 0, 0),
+                ('t3', '/music/3.mp3', 'Bachelorette', 0, 'Björk', 'album', 0, 0);",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+        // Leaves a gap in the rowids for VACUUM to close
+        store.delete_tracks(&["t1".into()]).await.unwrap();
+        sqlx::query("VACUUM").execute(&store.db).await.unwrap();
+
+        let results = store.search("bachelorette", 10).await.unwrap();
+        let ids: Vec<&str> = results.tracks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["t3"]);
+        let results = store.search("bjork", 10).await.unwrap();
+        assert_eq!(results.tracks.len(), 2);
+        assert_eq!(results.albums[0].id, "album");
+        assert_eq!(results.artists[0].name, "Björk");
+    }
+
+    #[tokio::test]
+    async fn refreshed_album_artwork_prefers_the_folder() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Artist');
+            INSERT INTO album (id, title, artist) VALUES ('a', 'A', 'Artist'), ('b', 'B', 'Artist');
+            INSERT INTO track (id, path, title, duration, artist, album_id, file_size, modified, artwork_path) VALUES
+                ('t1', '/music/a/1.mp3', 'One', 0, 'Artist', 'a', 0, 0, '/cache/embedded-a.jpg'),
+                ('t2', '/music/b/1.mp3', 'Two', 0, 'Artist', 'b', 0, 0, '/cache/embedded-b.jpg');",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+
+        let folder_artwork = HashMap::from([
+            ("a".to_string(), Some(PathBuf::from("/cache/folder-a.png"))),
+            ("b".to_string(), None),
+        ]);
+        store.refresh_album_artwork(&folder_artwork).await.unwrap();
+
+        let a = store.get_album("a").await.unwrap().unwrap();
+        assert_eq!(a.artwork_path, Some(PathBuf::from("/cache/folder-a.png")));
+        let b = store.get_album("b").await.unwrap().unwrap();
+        assert_eq!(b.artwork_path, Some(PathBuf::from("/cache/embedded-b.jpg")));
+    }
+
+    #[tokio::test]
+    async fn albums_sorted_by_artist_descending_reverse_artists_and_years() {
+        let store = test_store().await;
+        sqlx::query(
+            "INSERT INTO artist (name) VALUES ('Abba'), ('Blur');
+            INSERT INTO album (id, title, artist, year) VALUES
+                ('abba-1', 'Waterloo', 'Abba', 1974), ('abba-2', 'Arrival', 'Abba', 1976),
+                ('blur-1', 'Leisure', 'Blur', 1991), ('blur-2', 'Parklife', 'Blur', 1994);",
+        )
+        .execute(&store.db)
+        .await
+        .unwrap();
+        fill_sort_keys(&store.db).await.unwrap();
+
+        let by_artist = SmartSort {
+            field: SortField::Artist,
+            descending: true,
+        };
+        let albums = store
+            .get_albums(None, None, Some(&by_artist), 0, None)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = albums.items.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, ["blur-2", "blur-1", "abba-2", "abba-1"]);
     }
 }
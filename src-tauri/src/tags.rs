@@ -0,0 +1,366 @@
+//! Writing changes made in Tome back to the tags of the files, so other
+//! players see them and they survive the library being rebuilt.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use lofty::{
+    Accessor, AudioFile, ItemKey, ItemValue, Tag, TagItem, TagType, TaggedFile, TaggedFileExt,
+};
+use tauri::Manager;
+
+use crate::library::{clean_library, sync_files, ScanJob, ScanMonitor, SyncGuard};
+use crate::models::{MetadataEdit, MetadataEditResult, ScanError};
+use crate::store::Store;
+use crate::{Error, Result};
+
+/// Ratings in ID3v2 POPM frames belong to whoever wrote them. Most players
+/// read the ones Windows Media Player writes, so Tome writes them as it.
+const POPM_EMAIL: &[u8] = b"Windows Media Player 9 Series";
+
+/// POPM ratings go from 1 to 255, with 0 meaning unrated. These are the
+/// values Windows Media Player uses for each number of stars.
+const POPM_STARS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+/// Writes `edit` to the tags of each track, then reads them back into the
+/// library.
+#[tauri::command]
+pub async fn update_track_metadata(
+    window: tauri::Window,
+    store: tauri::State<'_, Store>,
+    track_ids: Vec<String>,
+    edit: MetadataEdit,
+) -> Result<MetadataEditResult> {
+    if edit.title.as_deref() == Some("") {
+        return Err(Error::Invalid("Tracks need a title".into()));
+    }
+    let job = window.state::<ScanJob>();
+    let guard = job.lock().await;
+    edit_tracks(&guard, &window, &store, &track_ids, move |path| {
+        write_metadata(path, &edit)
+    })
+    .await
+}
+
+/// Runs `edit` on the file of each track, then reads the files back into the
+/// library. A file that can't be edited doesn't stop the rest. Takes the
+/// guard from `ScanJob::lock`, so a scan or watched change can't sync the
+/// same files meanwhile.
+pub async fn edit_tracks<F>(
+    _sync: &SyncGuard<'_>,
+    window: &tauri::Window,
+    store: &Store,
+    track_ids: &[String],
+    edit: F,
+) -> Result<MetadataEditResult>
+where
+    F: Fn(&Path) -> Result<()> + Send + Sync + 'static,
+{
+    let mut tracks = vec![];
+    for id in track_ids {
+        let track = store
+            .get_track(id)
+            .await?
+            .ok_or_else(|| Error::NotFound("track", id.clone()))?;
+        tracks.push((track.id, track.path));
+    }
+
+    let edit = Arc::new(edit);
+    let mut written_ids = vec![];
+    let mut written = vec![];
+    let mut errors = vec![];
+    for (id, path) in tracks {
+        let edit = edit.clone();
+        let file = path.clone();
+        let res = tauri::async_runtime::spawn_blocking(move || edit(&file)).await?;
+        match res {
+            Ok(()) => {
+                written_ids.push(id);
+                written.push(path);
+            }
+            Err(e) => {
+                log::warn!("failed to write tags to {}: {}", path.display(), e);
+                errors.push(ScanError::new(&path, &e));
+            }
+        }
+    }
+
+    // The files have changed on disk, so syncing them reads the new tags and
+    // moves the tracks to whichever album and artist they now belong to. A
+    // file can be rewritten at the same size within the same second, so
+    // they're read whether or not they look changed.
+    store.forget_file_info(&written_ids).await?;
+    let changes = sync_files(store, &[], written, &mut ScanMonitor::silent()).await?;
+    clean_library(store).await?;
+    if !changes.is_empty() {
+        window.emit_all("library_changed", &changes)?;
+    }
+    Ok(MetadataEditResult { changes, errors })
+}
+
+/// Writes the fields set in `edit` to the file's main tag.
+pub fn write_metadata(path: &Path, edit: &MetadataEdit) -> Result<()> {
+    let mut file = lofty::read_from_path(path)?;
+    let tag = primary_tag_mut(&mut file);
+
+    if let Some(title) = &edit.title {
+        tag.set_title(title.clone());
+    }
+    match &edit.artist {
+        Some(Some(artist)) => tag.set_artist(artist.clone()),
+        Some(None) => tag.remove_artist(),
+        None => {}
+    }
+    match &edit.album_artist {
+        Some(Some(artist)) => {
+            tag.insert_text(ItemKey::AlbumArtist, artist.clone());
+        }
+        Some(None) => tag.remove_key(&ItemKey::AlbumArtist),
+        None => {}
+    }
+    if let Some(album) = &edit.album {
+        tag.set_album(album.clone());
+    }
+    match edit.track_number {
+        Some(Some(n)) => tag.set_track(n),
+        Some(None) => tag.remove_track(),
+        None => {}
+    }
+    match edit.cd_number {
+        Some(Some(n)) => tag.set_disk(n),
+        Some(None) => tag.remove_disk(),
+        None => {}
+    }
+    match edit.year {
+        Some(Some(year)) => tag.set_year(year),
+        Some(None) => tag.remove_year(),
+        None => {}
+    }
+    match &edit.genre {
+        Some(Some(genre)) => tag.set_genre(genre.clone()),
+        Some(None) => tag.remove_genre(),
+        None => {}
+    }
+    match edit.compilation {
+        Some(true) => {
+            tag.insert_text(ItemKey::FlagCompilation, "1".into());
+        }
+        Some(false) => tag.remove_key(&ItemKey::FlagCompilation),
+        None => {}
+    }
+
+    file.save_to_path(path)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_track_rating(
+    store: tauri::State<'_, Store>,
+    track_id: String,
+    rating: Option<u8>,
+    write_tags: bool,
+) -> Result<()> {
+    if rating.map_or(false, |r| r > 5) {
+        return Err(Error::Invalid("Ratings go from 0 to 5 stars".into()));
+    }
+    // Every format reads a rating of 0 back as unrated
+    let rating = rating.filter(|r| *r > 0);
+    let track = store
+        .get_track(&track_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("track", track_id.clone()))?;
+    if write_tags {
+        let path = track.path.clone();
+        tauri::async_runtime::spawn_blocking(move || write_rating(&path, rating)).await??;
+    }
+    store.set_rating(&track_id, rating).await
+}
+
+#[tauri::command]
+pub async fn set_track_loved(
+    store: tauri::State<'_, Store>,
+    track_id: String,
+    loved: bool,
+) -> Result<()> {
+    store.set_loved(&track_id, loved).await
+}
+
+/// Reads a rating in stars from a POPM frame or `RATING` field.
+pub fn read_rating(tag: &Tag) -> Option<u8> {
+    if tag.tag_type() == TagType::Id3v2 {
+        let popm = match tag.get(&ItemKey::Unknown("POPM".into()))?.value() {
+            ItemValue::Binary(b) => b,
+            _ => return None,
+        };
+        // The email is null terminated, and followed by the rating
+        let email_end = popm.iter().position(|b| *b == 0)?;
+        let rating = *popm.get(email_end + 1)?;
+        return match rating {
+            0 => None,
+            1..=31 => Some(1),
+            32..=95 => Some(2),
+            96..=159 => Some(3),
+            160..=223 => Some(4),
+            _ => Some(5),
+        };
+    }
+
+    // Some players write stars, and others a percentage
+    let rating: u32 = tag.get_string(&rating_key())?.trim().parse().ok()?;
+    let stars = if rating <= 5 {
+        rating as u8
+    } else {
+        ((rating.min(100) + 10) / 20) as u8
+    };
+    // As with POPM, 0 means unrated
+    Some(stars).filter(|s| *s > 0)
+}
+
+/// Writes a rating to the file's main tag, removing it if `None`. Fails
+/// rather than losing the rating if the tag can't hold it.
+pub fn write_rating(path: &Path, rating: Option<u8>) -> Result<()> {
+    let mut file = lofty::read_from_path(path)?;
+    let tag = primary_tag_mut(&mut file);
+    let tag_type = tag.tag_type();
+    set_rating(tag, rating)?;
+    file.save_to_path(path)?;
+
+    // Fields lofty has no name for in a format can be dropped when saving,
+    // so check the rating made it into the file
+    let rating = rating.filter(|stars| *stars > 0);
+    let saved = lofty::read_from_path(path)?;
+    if saved.primary_tag().and_then(read_rating) != rating {
+        return Err(Error::UnsupportedRating(format!("{:?}", tag_type)));
+    }
+    Ok(())
+}
+
+/// Sets the rating in `tag`, removing it if `None` or 0 stars. Only ID3v2,
+/// Vorbis comments and APE tags have somewhere to keep a rating.
+fn set_rating(tag: &mut Tag, rating: Option<u8>) -> Result<()> {
+    let rating = rating.filter(|stars| *stars > 0);
+    let inserted = match tag.tag_type() {
+        TagType::Id3v2 => {
+            let key = ItemKey::Unknown("POPM".into());
+            tag.remove_key(&key);
+            rating.map_or(true, |stars| {
+                let mut popm = POPM_EMAIL.to_vec();
+                popm.push(0);
+                popm.push(POPM_STARS[stars as usize]);
+                tag.insert(TagItem::new(key, ItemValue::Binary(popm)))
+            })
+        }
+        TagType::VorbisComments | TagType::Ape => {
+            tag.remove_key(&rating_key());
+            rating.map_or(true, |stars| {
+                tag.insert_text(rating_key(), (stars as u32 * 20).to_string())
+            })
+        }
+        // There's no rating to remove from tags that can't hold one
+        _ => rating.is_none(),
+    };
+    if !inserted {
+        return Err(Error::UnsupportedRating(format!("{:?}", tag.tag_type())));
+    }
+    Ok(())
+}
+
+/// Returns the file's main tag, adding an empty one if it has none.
+pub fn primary_tag_mut(file: &mut TaggedFile) -> &mut Tag {
+    let tag_type = file.primary_tag_type();
+    if file.tag(tag_type).is_none() {
+        file.insert_tag(Tag::new(tag_type));
+    }
+    file.tag_mut(tag_type).unwrap()
+}
+
+fn rating_key() -> ItemKey {
+    ItemKey::Unknown("RATING".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATINGS: [Option<u8>; 7] = [None, Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)];
+
+    /// Writes a short silent WAV file, which keeps its tags in ID3v2.
+    fn write_wav(path: &Path) {
+        let samples = vec![0u8; 800];
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes()); // byte rate
+        wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+        wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&samples);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn ratings_read_back_as_written() {
+        for tag_type in [TagType::Id3v2, TagType::VorbisComments, TagType::Ape] {
+            let mut tag = Tag::new(tag_type);
+            for rating in RATINGS {
+                set_rating(&mut tag, rating).unwrap();
+                // No stars is the same as no rating, whatever the format
+                let expected = rating.filter(|r| *r > 0);
+                assert_eq!(read_rating(&tag), expected, "{:?} {:?}", tag_type, rating);
+            }
+        }
+    }
+
+    #[test]
+    fn ratings_fail_in_tags_without_a_field_for_them() {
+        for tag_type in [TagType::Mp4Ilst, TagType::RiffInfo, TagType::Id3v1] {
+            let mut tag = Tag::new(tag_type);
+            assert!(
+                matches!(
+                    set_rating(&mut tag, Some(3)),
+                    Err(Error::UnsupportedRating(_))
+                ),
+                "{:?}",
+                tag_type
+            );
+            // Clearing a rating they can't have is fine
+            set_rating(&mut tag, None).unwrap();
+            set_rating(&mut tag, Some(0)).unwrap();
+        }
+    }
+
+    #[test]
+    fn ratings_survive_saving() {
+        let path = std::env::temp_dir().join("tome-tags-rating.wav");
+        write_wav(&path);
+        for rating in RATINGS {
+            write_rating(&path, rating).unwrap();
+            let file = lofty::read_from_path(&path).unwrap();
+            let tag = file.primary_tag().unwrap();
+            assert_eq!(tag.tag_type(), TagType::Id3v2);
+            assert_eq!(read_rating(tag), rating.filter(|r| *r > 0), "{:?}", rating);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn percentages_are_read_as_stars() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        for (value, stars) in [
+            ("0", None),
+            ("3", Some(3)),
+            ("20", Some(1)),
+            ("60", Some(3)),
+            ("100", Some(5)),
+        ] {
+            tag.insert_text(rating_key(), value.into());
+            assert_eq!(read_rating(&tag), stars, "{}", value);
+        }
+    }
+}
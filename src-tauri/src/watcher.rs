@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::library::{clean_library, get_file_filters, sync_files, ScanJob, ScanMonitor};
+use crate::store::Store;
+use crate::Result;
+
+/// How long the filesystem has to be quiet before a batch of changes is
+/// applied to the library
+const DEBOUNCE: Duration = Duration::from_millis(1500);
+
+pub struct LibraryWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+    roots: Mutex<HashSet<PathBuf>>,
+}
+
+impl LibraryWatcher {
+    /// Starts watching `root`. Returns whether it wasn't already watched.
+    pub fn watch(&self, root: &Path) -> Result<bool> {
+        let mut roots = self.roots.lock().unwrap();
+        if roots.contains(root) {
+            return Ok(false);
+        }
+        self.watcher
+            .lock()
+            .unwrap()
+            .watch(root, RecursiveMode::Recursive)?;
+        log::debug!("watching {}", root.display());
+        roots.insert(root.into());
+        Ok(true)
+    }
+
+    pub fn unwatch(&self, root: &Path) -> Result<()> {
+        if self.roots.lock().unwrap().remove(root) {
+            self.watcher.lock().unwrap().unwatch(root)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init_watcher<R: Runtime>(app: &tauri::App<R>) -> Result<()> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if !event.kind.is_access() => {
+            for path in event.paths {
+                let _ = tx.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("watch error: {}", e),
+    })?;
+
+    app.manage(LibraryWatcher {
+        watcher: Mutex::new(watcher),
+        roots: Mutex::new(HashSet::new()),
+    });
+    tauri::async_runtime::spawn(watch_library_roots(app.handle()));
+    tauri::async_runtime::spawn(process_events(app.handle(), rx));
+    Ok(())
+}
+
+async fn watch_library_roots<R: Runtime>(app: AppHandle<R>) {
+    let roots = match app.state::<Store>().get_library_roots().await {
+        Ok(roots) => roots,
+        Err(e) => {
+            log::error!("failed to load library roots: {}", e);
+            return;
+        }
+    };
+    let watcher = app.state::<LibraryWatcher>();
+    for root in roots {
+        if let Err(e) = watcher.watch(&root.path) {
+            log::error!("failed to watch {}: {}", root.path.display(), e);
+        }
+    }
+}
+
+async fn process_events<R: Runtime>(app: AppHandle<R>, mut rx: UnboundedReceiver<PathBuf>) {
+    while let Some(path) = rx.recv().await {
+        let mut paths = HashSet::from([path]);
+        // Keep collecting until the burst of events settles down
+        while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            paths.insert(path);
+        }
+
+        log::debug!("{} paths changed on disk", paths.len());
+        if let Err(e) = apply_changes(&app, paths).await {
+            log::error!("failed to update library: {}", e);
+        }
+    }
+}
+
+async fn apply_changes<R: Runtime>(app: &AppHandle<R>, paths: HashSet<PathBuf>) -> Result<()> {
+    let job = app.state::<ScanJob>();
+    let _guard = job.lock().await;
+    let store = app.state::<Store>();
+    let filters = get_file_filters(&store).await?;
+    let mut files = vec![];
+    let mut roots = vec![];
+    for path in paths {
+        let filter = match filters.iter().find(|f| f.contains(&path)) {
+            Some(f) => f,
+            None => continue,
+        };
+        // Everything under a root vanishes when its drive is unmounted, which
+        // isn't the same as the music being deleted
+        if !filter.root.is_dir() {
+            continue;
+        }
+        if path.is_dir() && filter.is_visible(&path) {
+            files.append(&mut filter.get_audio_files(&path));
+        } else if path.is_file() && filter.includes(&path) {
+            files.push(path.clone());
+        }
+        roots.push(path);
+    }
+
+    let changes = sync_files(&store, &roots, files, &mut ScanMonitor::silent()).await?;
+    if !changes.is_empty() {
+        // Removed tracks can leave albums, artists and artwork behind
+        clean_library(&store).await?;
+        app.emit_all("library_changed", changes)?;
+    }
+    Ok(())
+}
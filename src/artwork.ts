@@ -0,0 +1,12 @@
+import { convertFileSrc } from "@tauri-apps/api/tauri";
+
+export type ArtworkSize = "list" | "grid" | "full";
+
+// Returns the URL of cached artwork, scaled down to a thumbnail unless the
+// full size is asked for.
+export function artworkUrl(artworkPath: string | null | undefined, size: ArtworkSize = "full"): string {
+    if (!artworkPath) return "";
+    let name = artworkPath.split(/[\\/]/).pop() || "";
+    let url = convertFileSrc(name, "artwork");
+    return size == "full" ? url : `${url}?size=${size}`;
+}
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Album { id: string, title: string, artist: string, track_count: number, artwork_path: string | null, }
\ No newline at end of file
+export interface Album { id: string, title: string, artist: string, artwork_path: string | null, year: number | null, compilation: boolean, track_count: number, total_duration: number, disc_count: number, first_year: number | null, last_year: number | null, }
\ No newline at end of file
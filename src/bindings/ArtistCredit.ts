@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ArtistRole } from "./ArtistRole";
+
+export interface ArtistCredit { name: string, role: ArtistRole, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Genre { name: string, }
\ No newline at end of file
+export interface Genre { name: string, track_count: number, album_count: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LibraryRoot { path: string, follow_symlinks: boolean, include_hidden: boolean, allowed_extensions: Array<string>, denied_extensions: Array<string>, exclude_globs: Array<string>, }
\ No newline at end of file
@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ArtistCredit } from "./ArtistCredit";
 
-export interface Metadata { title: string, artist: string, song_artist: string | null, album: string, track_number: number | null, cd_number: number | null, year: number | null, genre: string | null, artwork_path: string | null, }
+export interface Metadata { title: string, artist: string, song_artist: string | null, album: string, track_number: number | null, cd_number: number | null, year: number | null, genre: string | null, genres: Array<string>, credits: Array<ArtistCredit>, artwork_path: string | null, album_artist: string | null, compilation: boolean, musicbrainz_album_id: string | null, rating: number | null, loved: boolean, }
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface MetadataEdit { title?: string, artist?: string | null, album_artist?: string | null, album?: string, track_number?: number | null, cd_number?: number | null, year?: number | null, genre?: string | null, compilation?: boolean, }
\ No newline at end of file
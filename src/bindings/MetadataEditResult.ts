@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LibraryChanges } from "./LibraryChanges";
+import type { ScanError } from "./ScanError";
+
+export interface MetadataEditResult { changes: LibraryChanges, errors: Array<ScanError>, }
\ No newline at end of file
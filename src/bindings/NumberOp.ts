@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type NumberOp = "is" | "is_not" | "greater_than" | "at_least" | "less_than" | "at_most";
\ No newline at end of file
@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SmartRules } from "./SmartRules";
 
-export interface Playlist { title: string, }
\ No newline at end of file
+export interface Playlist { id: number, title: string, rules: SmartRules | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Track } from "./Track";
+
+export interface PlaylistEntry { id: number, position: number, track: Track, }
\ No newline at end of file
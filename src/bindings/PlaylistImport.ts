@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Playlist } from "./Playlist";
+
+export interface PlaylistImport { playlist: Playlist, matched: number, unmatched: Array<string>, }
\ No newline at end of file
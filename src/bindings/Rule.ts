@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { NumberField } from "./NumberField";
+import type { NumberOp } from "./NumberOp";
+import type { TextField } from "./TextField";
+import type { TextOp } from "./TextOp";
+
+export type Rule = { type: "all", rules: Array<Rule>, } | { type: "any", rules: Array<Rule>, } | { type: "text", field: TextField, op: TextOp, value: string, } | { type: "number", field: NumberField, op: NumberOp, value: number, } | { type: "between", field: NumberField, min: number, max: number, } | { type: "added_within", days: number, } | { type: "played_within", days: number, } | { type: "loved", loved: boolean, } | { type: "not", rule: Rule, };
\ No newline at end of file
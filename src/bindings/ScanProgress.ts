@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ScanProgress { discovered: number, processed: number, current_path: string | null, errors: number, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CleanupSummary } from "./CleanupSummary";
+import type { ScanProgress } from "./ScanProgress";
+
+export interface ScanSummary { progress: ScanProgress, cancelled: boolean, error: string | null, cleanup: CleanupSummary | null, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Album } from "./Album";
+import type { Artist } from "./Artist";
+import type { Track } from "./Track";
+
+export interface SearchResults { tracks: Array<Track>, albums: Array<Album>, artists: Array<Artist>, }
\ No newline at end of file
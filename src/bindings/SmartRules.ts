@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Rule } from "./Rule";
+import type { SmartSort } from "./SmartSort";
+
+export interface SmartRules { rule: Rule, limit: number | null, sort: SmartSort | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type SortField = "title" | "artist" | "album" | "year" | "duration" | "added_at" | "play_count" | "last_played" | "rating" | "random";
\ No newline at end of file
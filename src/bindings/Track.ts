@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { Metadata } from "./Metadata";
+import type { TrackStats } from "./TrackStats";
 
-export interface Track { id: string, path: string, metadata: Metadata, duration: number, }
\ No newline at end of file
+export interface Track { id: string, path: string, metadata: Metadata, duration: number, stats: TrackStats, }
\ No newline at end of file
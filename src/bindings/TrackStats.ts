@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface TrackStats { play_count: number, skip_count: number, last_played: number | null, }
\ No newline at end of file
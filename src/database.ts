@@ -4,27 +4,35 @@ import { writable, type Writable } from 'svelte/store';
 import type { Album } from './bindings/Album';
 import type { Artist } from './bindings/Artist';
 import type { Genre } from './bindings/Genre';
+import type { LibraryChanges } from './bindings/LibraryChanges';
+import type { Page } from './bindings/Page';
 import type { Playlist } from './bindings/Playlist';
+import type { SearchResults } from './bindings/SearchResults';
+import type { SmartSort } from './bindings/SmartSort';
 import type { Track } from './bindings/Track';
 
-interface Query {
-    where(predicate: string): Query;
-}
-
 class Database {
-    tracks: Writable<Track[]>;
-    artists: Writable<Artist[]>;
-    albums: Writable<Album[]>;
-    genres: Writable<Genre[]>;
-    playlists: Writable<Playlist[]>;
+    tracks: Writable<Track[]> = writable([]);
+    artists: Writable<Artist[]> = writable([]);
+    albums: Writable<Album[]> = writable([]);
+    genres: Writable<Genre[]> = writable([]);
+    playlists: Writable<Playlist[]> = writable([]);
 
     unlistenDb: UnlistenFn;
 
     async initialise() {
-        this.unlistenDb = await listen<Track[]>("track_update", e => {
-            this.tracks.set(e.payload);
+        this.unlistenDb = await listen<LibraryChanges>("library_changed", async _ => {
+            this.tracks.set((await invoke<Page<Track>>("get_tracks")).items);
         });
     }
+
+    async search(query: string, limit?: number): Promise<SearchResults> {
+        return await invoke<SearchResults>("search", { query, limit });
+    }
+
+    async queryTracks(query: string, sort?: SmartSort, offset?: number, limit?: number): Promise<Page<Track>> {
+        return await invoke<Page<Track>>("query_tracks", { query, sort, offset, limit });
+    }
 }
 
 export default new Database();
@@ -24,6 +24,10 @@ class Player {
         this.audioFile = new Audio();
         this.audioFile.src = "";
         this.audioFile.addEventListener("ended", async () => {
+            let track = get(currentTrack);
+            if (track) {
+                await invoke("record_play", { trackId: track.id, listened: Math.round(this.audioFile.currentTime) });
+            }
             await this.playNext();
         });
         this.audioFile.volume = perceivedLoudness(get(volume));
@@ -76,6 +80,10 @@ class Player {
     }
 
     async playTrack(track: Track) {
+        let previous = get(currentTrack);
+        if (previous && !this.audioFile.ended) {
+            await invoke("record_skip", { trackId: previous.id, listened: Math.round(this.audioFile.currentTime) });
+        }
         await invoke("set_metadata", { track });
         isLoading.set(true);
         isPlaying.set(true);
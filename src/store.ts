@@ -2,6 +2,7 @@ import { invoke } from "@tauri-apps/api";
 import { writable, derived, type Writable } from "svelte/store";
 import { tweened } from "svelte/motion";
 import type { Album } from "./bindings/Album";
+import type { Page } from "./bindings/Page";
 import type { Track } from "./bindings/Track";
 
 export const currentTime = writable(0);
@@ -23,8 +24,8 @@ export const isMuted = writable(false);
 export const selectedArtist: Writable<string | null> = writable(null);
 export const selectedAlbum: Writable<Album | null> = writable(null);
 export const albums = derived<Writable<string | null>, Album[]>(selectedArtist, ($selectedArtist, set) => {
-    invoke<Album[]>("get_albums", { artist: $selectedArtist })
-        .then(as => set(as.sort((a, b) => a.title.localeCompare(b.title))));
+    invoke<Page<Album>>("get_albums", { artist: $selectedArtist, sort: { field: "title", descending: false } })
+        .then(page => set(page.items));
     return () => { set = () => {} };
 }, []);
 